@@ -1,6 +1,6 @@
 "use strict";
 // eslint-disable-next-line @typescript-eslint/no-unused-vars
-function registerModification(manifestDirBlobURL, license, cargoAddCommand, dependencyUL, codeSizeUnmodified, verifiedWith) {
+function registerModification(manifestDirBlobURL, license, cargoAddCommand, dependencyUL, codeSizeUnmodified, verifiedWith, examples, codeSizeCommentsStripped, devDependencyUL, buildDependencyUL, lastVerified, freshlyTested, codeSizeFormatted, codeSizeMinified) {
     if (!window.location.pathname.endsWith("/index.html")) {
         return;
     }
@@ -11,12 +11,24 @@ function registerModification(manifestDirBlobURL, license, cargoAddCommand, depe
         }
         downgradeSectionHeaders(docblock);
         docblock.prepend(createHeader("Description", "description"));
-        docblock.prepend(createVerifiedWithSection(verifiedWith));
+        if (examples !== null) {
+            docblock.prepend(createExamplesSection(examples));
+            docblock.prepend(createHeader("Examples", "examples"));
+        }
+        docblock.prepend(createVerifiedWithSection(verifiedWith, lastVerified, freshlyTested));
         docblock.prepend(createHeader("Verified with", "verified-with"));
         if (codeSizeUnmodified !== null) {
-            docblock.prepend(createCodeSizeSection(codeSizeUnmodified));
+            docblock.prepend(createCodeSizeSection(codeSizeUnmodified, codeSizeCommentsStripped, codeSizeFormatted, codeSizeMinified));
             docblock.prepend(createHeader("Code size", "code-size"));
         }
+        if (buildDependencyUL !== null) {
+            docblock.prepend(createDependenciesSection(buildDependencyUL));
+            docblock.prepend(createHeader("Build-dependencies", "build-dependencies"));
+        }
+        if (devDependencyUL !== null) {
+            docblock.prepend(createDependenciesSection(devDependencyUL));
+            docblock.prepend(createHeader("Dev-dependencies", "dev-dependencies"));
+        }
         docblock.prepend(createDependenciesSection(dependencyUL));
         docblock.prepend(createHeader("Dependencies", "dependencies"));
         docblock.prepend(createCargoAddCommandSection(cargoAddCommand));
@@ -80,7 +92,7 @@ function downgradeSectionHeaders(docblock) {
         sectionHeader.replaceWith(replacement);
     });
 }
-function createVerifiedWithSection(verifiedWith) {
+function createVerifiedWithSection(verifiedWith, lastVerified, freshlyTested) {
     const div = document.createElement("div");
     switch (verifiedWith.length) {
         case 0: {
@@ -95,9 +107,16 @@ function createVerifiedWithSection(verifiedWith) {
         default:
             div.append(createHeavyCheckMark(), " This library verified with " + verifiedWith.length + " solutions.");
     }
+    if (verifiedWith.length > 0) {
+        const judges = [...new Set(verifiedWith.map(([, , judge]) => judge))];
+        const p = document.createElement("p");
+        p.append("Verified on: " + judges.sort().join(", "));
+        div.append(p);
+    }
     const ul = document.createElement("ul");
-    for (const [problemURL, blobURL] of verifiedWith) {
+    for (const [problemURL, blobURL, judge] of verifiedWith) {
         const li = document.createElement("li");
+        li.append("[" + judge + "] ");
         const a1 = document.createElement("a");
         a1.setAttribute("href", problemURL);
         a1.append(problemURL);
@@ -108,8 +127,29 @@ function createVerifiedWithSection(verifiedWith) {
         ul.append(li);
     }
     div.append(ul);
+    const p = document.createElement("p");
+    p.append("Last verified: " + new Date(lastVerified).toISOString());
+    if (!freshlyTested) {
+        p.append(" (the tests were not run)");
+    }
+    div.append(p);
     return div;
 }
+function createExamplesSection(examples) {
+    if (examples.length === 0) {
+        return "No examples.";
+    }
+    const ul = document.createElement("ul");
+    for (const [name, blobURL] of examples) {
+        const li = document.createElement("li");
+        const a = document.createElement("a");
+        a.setAttribute("href", blobURL);
+        a.append(name);
+        li.append(a);
+        ul.append(li);
+    }
+    return ul;
+}
 function createHeavyCheckMark() {
     return createMark("https://github.githubassets.com/images/icons/emoji/unicode/2714.png", "✔");
 }
@@ -125,20 +165,10 @@ function createMark(src, char) {
     mark.setAttribute("height", "20");
     return mark;
 }
-function createCodeSizeSection(codeSizeUnmodified) {
+function createCodeSizeSection(codeSizeUnmodified, codeSizeCommentsStripped, codeSizeFormatted, codeSizeMinified) {
     const ul = document.createElement("ul");
     const li1 = document.createElement("li");
-    li1.append("unmodified: ");
-    if (typeof codeSizeUnmodified === "number") {
-        const div = Math.floor(codeSizeUnmodified / 1024);
-        const rem = codeSizeUnmodified % 1024;
-        li1.append("" + div + "." + Math.floor((10 * rem) / 1024) + " KiB");
-    }
-    else {
-        const code = document.createElement("code");
-        code.append(codeSizeUnmodified);
-        li1.append(code);
-    }
+    li1.append("unmodified: ", formatCodeSize(codeSizeUnmodified));
     li1.append(" + (not yet implemented) KiB");
     const li2 = document.createElement("li");
     const li3 = document.createElement("li");
@@ -146,22 +176,48 @@ function createCodeSizeSection(codeSizeUnmodified) {
     const code2 = document.createElement("code");
     code1.append("#[cfg]");
     code2.append("#[cfg]");
-    li2.append(code1, " resolved + (doc-)comment removed + Rustfmt: (not yet implemented)");
-    li3.append(code2, " resolved + doc-comment removed + minified: (not yet implemented)");
+    li2.append(code1, " resolved + (doc-)comment removed: ", codeSizeCommentsStripped === null
+        ? "(not yet implemented)"
+        : formatCodeSize(codeSizeCommentsStripped));
+    li3.append(code2, " resolved + (doc-)comment removed + minified: ", codeSizeMinified === null
+        ? "(not yet implemented)"
+        : formatCodeSize(codeSizeMinified));
     ul.append(li1, li2, li3);
+    if (codeSizeFormatted !== null) {
+        const [codeSize, rustfmtApplied] = codeSizeFormatted;
+        const li4 = document.createElement("li");
+        li4.append("formatted with rustfmt: ", formatCodeSize(codeSize));
+        if (!rustfmtApplied) {
+            li4.append(" (rustfmt was unavailable. measured without formatting)");
+        }
+        ul.append(li4);
+    }
     return ul;
 }
+function formatCodeSize(codeSize) {
+    if (typeof codeSize === "number") {
+        const div = Math.floor(codeSize / 1024);
+        const rem = codeSize % 1024;
+        return "" + div + "." + Math.floor((10 * rem) / 1024) + " KiB";
+    }
+    const code = document.createElement("code");
+    code.append(codeSize);
+    return code;
+}
 function createDependenciesSection(items) {
     if (items.length === 0) {
         return "No dependencies.";
     }
     const ul = document.createElement("ul");
-    for (const [text, href] of items) {
+    for (const [text, href, features] of items) {
         const li = document.createElement("li");
         const a = document.createElement("a");
         a.setAttribute("href", href);
         a.append(text);
         li.append(a);
+        if (features !== null) {
+            li.append(" (" + features + ")");
+        }
         ul.append(li);
     }
     return ul;
@@ -3,9 +3,17 @@ function registerModification(
   manifestDirBlobURL: string,
   license: string | null,
   cargoAddCommand: string,
-  dependencyUL: [string, string][],
+  dependencyUL: [string, string, string | null][],
   codeSizeUnmodified: number | string | null,
-  verifiedWith: [string, string][]
+  verifiedWith: [string, string, string][],
+  examples: [string, string][] | null,
+  codeSizeCommentsStripped: number | string | null,
+  devDependencyUL: [string, string, string | null][] | null,
+  buildDependencyUL: [string, string, string | null][] | null,
+  lastVerified: number,
+  freshlyTested: boolean,
+  codeSizeFormatted: [number | string, boolean] | null,
+  codeSizeMinified: number | string | null
 ): void {
   if (!window.location.pathname.endsWith("/index.html")) {
     return;
@@ -17,12 +25,35 @@ function registerModification(
     }
     downgradeSectionHeaders(docblock);
     docblock.prepend(createHeader("Description", "description"));
-    docblock.prepend(createVerifiedWithSection(verifiedWith));
+    if (examples !== null) {
+      docblock.prepend(createExamplesSection(examples));
+      docblock.prepend(createHeader("Examples", "examples"));
+    }
+    docblock.prepend(
+      createVerifiedWithSection(verifiedWith, lastVerified, freshlyTested)
+    );
     docblock.prepend(createHeader("Verified with", "verified-with"));
     if (codeSizeUnmodified !== null) {
-      docblock.prepend(createCodeSizeSection(codeSizeUnmodified));
+      docblock.prepend(
+        createCodeSizeSection(
+          codeSizeUnmodified,
+          codeSizeCommentsStripped,
+          codeSizeFormatted,
+          codeSizeMinified
+        )
+      );
       docblock.prepend(createHeader("Code size", "code-size"));
     }
+    if (buildDependencyUL !== null) {
+      docblock.prepend(createDependenciesSection(buildDependencyUL));
+      docblock.prepend(
+        createHeader("Build-dependencies", "build-dependencies")
+      );
+    }
+    if (devDependencyUL !== null) {
+      docblock.prepend(createDependenciesSection(devDependencyUL));
+      docblock.prepend(createHeader("Dev-dependencies", "dev-dependencies"));
+    }
     docblock.prepend(createDependenciesSection(dependencyUL));
     docblock.prepend(createHeader("Dependencies", "dependencies"));
     docblock.prepend(createCargoAddCommandSection(cargoAddCommand));
@@ -93,7 +124,9 @@ function downgradeSectionHeaders(docblock: Element): void {
 }
 
 function createVerifiedWithSection(
-  verifiedWith: [string, string][]
+  verifiedWith: [string, string, string][],
+  lastVerified: number,
+  freshlyTested: boolean
 ): HTMLDivElement {
   const div = document.createElement("div");
   switch (verifiedWith.length) {
@@ -115,9 +148,16 @@ function createVerifiedWithSection(
         " This library verified with " + verifiedWith.length + " solutions."
       );
   }
+  if (verifiedWith.length > 0) {
+    const judges = [...new Set(verifiedWith.map(([, , judge]) => judge))];
+    const p = document.createElement("p");
+    p.append("Verified on: " + judges.sort().join(", "));
+    div.append(p);
+  }
   const ul = document.createElement("ul");
-  for (const [problemURL, blobURL] of verifiedWith) {
+  for (const [problemURL, blobURL, judge] of verifiedWith) {
     const li = document.createElement("li");
+    li.append("[" + judge + "] ");
     const a1 = document.createElement("a");
     a1.setAttribute("href", problemURL);
     a1.append(problemURL);
@@ -128,9 +168,33 @@ function createVerifiedWithSection(
     ul.append(li);
   }
   div.append(ul);
+  const p = document.createElement("p");
+  p.append("Last verified: " + new Date(lastVerified).toISOString());
+  if (!freshlyTested) {
+    p.append(" (the tests were not run)");
+  }
+  div.append(p);
   return div;
 }
 
+function createExamplesSection(
+  examples: [string, string][]
+): HTMLUListElement | string {
+  if (examples.length === 0) {
+    return "No examples.";
+  }
+  const ul = document.createElement("ul");
+  for (const [name, blobURL] of examples) {
+    const li = document.createElement("li");
+    const a = document.createElement("a");
+    a.setAttribute("href", blobURL);
+    a.append(name);
+    li.append(a);
+    ul.append(li);
+  }
+  return ul;
+}
+
 function createHeavyCheckMark(): HTMLImageElement {
   return createMark(
     "https://github.githubassets.com/images/icons/emoji/unicode/2714.png",
@@ -156,20 +220,14 @@ function createMark(src: string, char: string): HTMLImageElement {
 }
 
 function createCodeSizeSection(
-  codeSizeUnmodified: number | string
+  codeSizeUnmodified: number | string,
+  codeSizeCommentsStripped: number | string | null,
+  codeSizeFormatted: [number | string, boolean] | null,
+  codeSizeMinified: number | string | null
 ): HTMLElement {
   const ul = document.createElement("ul");
   const li1 = document.createElement("li");
-  li1.append("unmodified: ");
-  if (typeof codeSizeUnmodified === "number") {
-    const div = Math.floor(codeSizeUnmodified / 1024);
-    const rem = codeSizeUnmodified % 1024;
-    li1.append("" + div + "." + Math.floor((10 * rem) / 1024) + " KiB");
-  } else {
-    const code = document.createElement("code");
-    code.append(codeSizeUnmodified);
-    li1.append(code);
-  }
+  li1.append("unmodified: ", formatCodeSize(codeSizeUnmodified));
   li1.append(" + (not yet implemented) KiB");
   const li2 = document.createElement("li");
   const li3 = document.createElement("li");
@@ -179,29 +237,58 @@ function createCodeSizeSection(
   code2.append("#[cfg]");
   li2.append(
     code1,
-    " resolved + (doc-)comment removed + Rustfmt: (not yet implemented)"
+    " resolved + (doc-)comment removed: ",
+    codeSizeCommentsStripped === null
+      ? "(not yet implemented)"
+      : formatCodeSize(codeSizeCommentsStripped)
   );
   li3.append(
     code2,
-    " resolved + doc-comment removed + minified: (not yet implemented)"
+    " resolved + (doc-)comment removed + minified: ",
+    codeSizeMinified === null
+      ? "(not yet implemented)"
+      : formatCodeSize(codeSizeMinified)
   );
   ul.append(li1, li2, li3);
+  if (codeSizeFormatted !== null) {
+    const [codeSize, rustfmtApplied] = codeSizeFormatted;
+    const li4 = document.createElement("li");
+    li4.append("formatted with rustfmt: ", formatCodeSize(codeSize));
+    if (!rustfmtApplied) {
+      li4.append(" (rustfmt was unavailable. measured without formatting)");
+    }
+    ul.append(li4);
+  }
   return ul;
 }
 
+function formatCodeSize(codeSize: number | string): string | HTMLElement {
+  if (typeof codeSize === "number") {
+    const div = Math.floor(codeSize / 1024);
+    const rem = codeSize % 1024;
+    return "" + div + "." + Math.floor((10 * rem) / 1024) + " KiB";
+  }
+  const code = document.createElement("code");
+  code.append(codeSize);
+  return code;
+}
+
 function createDependenciesSection(
-  items: [string, string][]
+  items: [string, string, string | null][]
 ): HTMLUListElement | string {
   if (items.length === 0) {
     return "No dependencies.";
   }
   const ul = document.createElement("ul");
-  for (const [text, href] of items) {
+  for (const [text, href, features] of items) {
     const li = document.createElement("li");
     const a = document.createElement("a");
     a.setAttribute("href", href);
     a.append(text);
     li.append(a);
+    if (features !== null) {
+      li.append(" (" + features + ")");
+    }
     ul.append(li);
   }
   return ul;
@@ -0,0 +1,282 @@
+use crate::{
+    shell::Shell,
+    workspace::{self, normal_deps, resolved_features, PackageExt as _, TargetExt as _},
+};
+use anyhow::{anyhow, bail, Context as _};
+use camino::Utf8Path;
+use cargo_metadata as cm;
+use itertools::Itertools as _;
+use maplit::btreeset;
+use std::path::Path;
+
+/// Inlines the file modules of the crate whose root is `src_path`, removing `#[cfg(test)]`
+/// modules.
+///
+/// The features resolved by `cargo metadata` are enabled.
+///
+/// The inlined modules are indented with `indent_width` spaces per level. `0` keeps the original
+/// formatting.
+pub fn expand_mods(src_path: &Utf8Path, indent_width: usize) -> anyhow::Result<String> {
+    let canonical_src_path = &dunce::canonicalize(src_path)
+        .with_context(|| format!("could not canonicalize `{}`", src_path))?;
+    let manifest_dir = canonical_src_path
+        .parent()
+        .with_context(|| format!("`{}` has no parent directory", src_path))?;
+    let metadata = &workspace::cargo_metadata(&workspace::locate_project(manifest_dir)?, &[])?;
+    let package = metadata
+        .packages
+        .iter()
+        .find(|p| {
+            p.targets.iter().any(
+                |t| matches!(dunce::canonicalize(&t.src_path), Ok(p) if p == *canonical_src_path),
+            )
+        })
+        .with_context(|| format!("`{}` is not the root of any target", src_path))?;
+
+    crate::rust::expand_mods(
+        src_path,
+        Some(resolved_features(metadata, &package.id)),
+        indent_width,
+    )
+    .map_err(|err| anyhow!("{}", err).context(format!("could not expand `{}`", src_path)))
+}
+
+/// Prints the bin target `bin` into one file, with the path dependencies inlined as modules.
+///
+/// The modules are indented with `indent_width` spaces per level. `0` keeps the original
+/// formatting.
+pub fn bundle(
+    bin: &str,
+    package: Option<&str>,
+    indent_width: usize,
+    cwd: &Path,
+    shell: &mut Shell,
+) -> anyhow::Result<()> {
+    let metadata = &workspace::cargo_metadata(&workspace::locate_project(cwd)?, &[])?;
+    let (ws_member, bin_target) = find_target(metadata, package, Some(bin))?;
+
+    let mut code = crate::rust::expand_mods(
+        &bin_target.src_path,
+        Some(resolved_features(metadata, &ws_member.id)),
+        indent_width,
+    )
+    .map_err(|e| anyhow!("{}", e))?;
+
+    let normal_deps = &normal_deps(metadata);
+    let mut deps = btreeset!();
+    let stack = &mut normal_deps[&ws_member.id]
+        .iter()
+        .map(|(_, pkg)| *pkg)
+        .collect::<Vec<_>>();
+    while let Some(package_id) = stack.pop() {
+        if metadata[package_id].source.is_none() && deps.insert(package_id) {
+            stack.extend(normal_deps[package_id].iter().map(|(_, pkg)| *pkg));
+        }
+    }
+
+    // The bundled dependencies with the names in the manifest, which differ from the crate names
+    // if renamed with `package = "..."`.
+    let bundled_deps = |package_id| {
+        normal_deps[package_id]
+            .iter()
+            .filter(|(_, dep)| deps.contains(dep))
+            .flat_map(|(name, dep)| {
+                Some((name.as_str(), metadata[*dep].lib_target()?.crate_name()))
+            })
+    };
+    let use_decl = |crate_name: &str, name: &str, indent: &str| {
+        let alias = if name == crate_name {
+            "".to_owned()
+        } else {
+            format!(" as {}", name)
+        };
+        format!(
+            "\n{indent}#[allow(unused_imports)]\n{indent}use crate::{}{};\n",
+            crate_name,
+            alias,
+            indent = indent,
+        )
+    };
+
+    // The modules are placed at the root, so only the renamed ones need `use` there.
+    for (name, crate_name) in bundled_deps(&ws_member.id) {
+        if name != crate_name {
+            code += &use_decl(&crate_name, name, "");
+        }
+    }
+
+    for package_id in &deps {
+        let package = &metadata[*package_id];
+        let lib_target = match package.lib_target() {
+            Some(lib_target) => lib_target,
+            None => continue,
+        };
+        let crate_name = lib_target.crate_name();
+        let lib = crate::rust::expand_mods(
+            &lib_target.src_path,
+            Some(resolved_features(metadata, &package.id)),
+            indent_width,
+        )
+        .and_then(|lib| crate::rust::prefix_crate_paths(&lib, &crate_name))
+        .map_err(|e| anyhow!("{}", e))
+        .with_context(|| format!("could not bundle `{}`", package.name))?;
+
+        code += &format!("\npub mod {} {{\n", crate_name);
+        code += &crate::rust::indent_code(&lib, 1, indent_width);
+        // Placed after the inner attributes such as `//!`.
+        for (name, crate_name) in bundled_deps(package_id) {
+            code += &use_decl(&crate_name, name, &" ".repeat(indent_width));
+        }
+        code += "}\n";
+    }
+
+    let code = crate::rust::remove_test_items(&code)
+        .map_err(|e| anyhow!("{}", e))
+        .with_context(|| "could not remove the test items")?;
+
+    shell.out().write_all(code.as_ref())?;
+    shell.out().flush()?;
+    Ok(())
+}
+
+/// Prints the bin target `bin`, or the lib target if `bin` is `None`, with the file modules
+/// inlined.
+pub fn expand(
+    bin: Option<&str>,
+    package: Option<&str>,
+    strip_comments: bool,
+    cwd: &Path,
+    shell: &mut Shell,
+) -> anyhow::Result<()> {
+    let metadata = &workspace::cargo_metadata(&workspace::locate_project(cwd)?, &[])?;
+    let (ws_member, target) = find_target(metadata, package, bin)?;
+
+    let mut code = crate::rust::expand_mods(
+        &target.src_path,
+        Some(resolved_features(metadata, &ws_member.id)),
+        4,
+    )
+    .map_err(|e| anyhow!("{}", e))?;
+    if strip_comments {
+        code = crate::rust::strip_comments(&code);
+    }
+
+    shell.out().write_all(code.as_ref())?;
+    shell.out().flush()?;
+    Ok(())
+}
+
+/// Finds the workspace member with the bin target `bin`, or with a lib target if `bin` is `None`.
+fn find_target<'a>(
+    metadata: &'a cm::Metadata,
+    package: Option<&str>,
+    bin: Option<&str>,
+) -> anyhow::Result<(&'a cm::Package, &'a cm::Target)> {
+    let candidates = metadata
+        .workspace_members
+        .iter()
+        .map(|id| &metadata[id])
+        .filter(|p| matches!(package, Some(name) if p.name == name) || package.is_none())
+        .flat_map(|p| {
+            let target = match bin {
+                Some(bin) => p.bin_target(bin).ok(),
+                None => p.lib_target(),
+            };
+            Some((p, target?))
+        })
+        .collect::<Vec<_>>();
+    let target = match bin {
+        Some(bin) => format!("bin target named `{}`", bin),
+        None => "lib target".to_owned(),
+    };
+    match *candidates {
+        [candidate] => Ok(candidate),
+        [] => match package {
+            Some(package) => bail!("no {} in `{}`", target, package),
+            None => bail!("no {}", target),
+        },
+        _ => bail!(
+            "multiple packages have a {}. specify one of {} with `--package`",
+            target,
+            candidates
+                .iter()
+                .format_with(", ", |(p, _), f| f(&format_args!("`{}`", p.name))),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::tempdir_with_files;
+    use camino::Utf8Path;
+
+    #[test]
+    fn expand_mods_reads_features() -> anyhow::Result<()> {
+        let tempdir = tempdir_with_files(&[
+            (
+                "Cargo.toml",
+                r#"[package]
+name = "a"
+version = "0.0.0"
+edition = "2015"
+
+[features]
+default = ["x"]
+x = []
+y = []
+"#,
+            ),
+            (
+                "src/lib.rs",
+                "#[cfg(feature = \"x\")]\nmod x;\n#[cfg(feature = \"y\")]\nmod y;\n",
+            ),
+            // `mod z;` in `x.rs` is `src/x/z.rs` on edition 2015 as well.
+            ("src/x.rs", "mod z;\n"),
+            ("src/x/z.rs", "fn z() {}\n"),
+        ])?;
+
+        let src_path = tempdir.path().join("src").join("lib.rs");
+        let src_path = Utf8Path::from_path(&src_path).unwrap();
+        let code = super::expand_mods(src_path, 4)?;
+        assert!(code.contains("fn z() {}"), "{}", code);
+        assert!(!code.contains("mod y"), "{}", code);
+        Ok(())
+    }
+
+    #[test]
+    fn expand_mods_reads_features_enabled_by_dependents() -> anyhow::Result<()> {
+        let tempdir = tempdir_with_files(&[
+            ("Cargo.toml", "[workspace]\nmembers = [\"a\", \"b\"]\n"),
+            (
+                "a/Cargo.toml",
+                r#"[package]
+name = "a"
+version = "0.0.0"
+edition = "2018"
+
+[features]
+y = []
+"#,
+            ),
+            ("a/src/lib.rs", "#[cfg(feature = \"y\")]\nmod y {}\n"),
+            (
+                "b/Cargo.toml",
+                r#"[package]
+name = "b"
+version = "0.0.0"
+edition = "2018"
+
+[dependencies]
+a = { path = "../a", features = ["y"] }
+"#,
+            ),
+            ("b/src/lib.rs", ""),
+        ])?;
+
+        let src_path = tempdir.path().join("a").join("src").join("lib.rs");
+        let src_path = Utf8Path::from_path(&src_path).unwrap();
+        let code = super::expand_mods(src_path, 4)?;
+        assert!(code.contains("mod y"), "{}", code);
+        Ok(())
+    }
+}
@@ -0,0 +1,335 @@
+use crate::shell::Shell;
+use anyhow::Context as _;
+use cargo_metadata as cm;
+use fs2::FileExt as _;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+pub(crate) fn cache_dir() -> anyhow::Result<PathBuf> {
+    Ok(dirs_next::cache_dir()
+        .with_context(|| "could not find the cache directory")?
+        .join("cargo-cpl"))
+}
+
+/// Locks `dir` so that other cargo-cpl processes do not modify it at the same time, waiting for
+/// them if they hold the lock. The lock is released when the returned file is dropped.
+pub(crate) fn lock_dir(dir: &Path, shell: &mut Shell) -> anyhow::Result<fs::File> {
+    xshell::mkdir_p(dir)?;
+    let path = &dir.join(".cargo-cpl.lock");
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("could not open `{}`", path.display()))?;
+    match file.try_lock_exclusive() {
+        Ok(()) => {}
+        Err(err) if err.raw_os_error() == fs2::lock_contended_error().raw_os_error() => {
+            shell.status(
+                "Blocking",
+                format!("waiting for another cargo-cpl run on `{}`", dir.display()),
+            )?;
+            file.lock_exclusive()
+                .with_context(|| format!("could not lock `{}`", path.display()))?;
+        }
+        Err(err) => {
+            return Err(err).with_context(|| format!("could not lock `{}`", path.display()));
+        }
+    }
+    Ok(file)
+}
+
+/// Removes the cache directory of cargo-cpl.
+pub fn clean(dry_run: bool, shell: &mut Shell) -> anyhow::Result<()> {
+    let cache_dir = cache_dir()?;
+    if !cache_dir.exists() {
+        return Ok(());
+    }
+    for path in xshell::read_dir(cache_dir)? {
+        if dry_run {
+            shell.status("Would remove", path.display())?;
+        } else {
+            shell.status("Removing", path.display())?;
+            xshell::rm_rf(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Results of the previous tests, keyed with manifest paths and bin names.
+#[derive(Default, Deserialize, Serialize)]
+pub(crate) struct Checkpoints(BTreeMap<String, BTreeMap<String, Checkpoint>>);
+
+#[derive(Deserialize, Serialize)]
+struct Checkpoint {
+    source_hash: String,
+    passed: bool,
+}
+
+impl Checkpoints {
+    fn path() -> anyhow::Result<PathBuf> {
+        Ok(cache_dir()?.join("checkpoints.json"))
+    }
+
+    /// Loads the checkpoints, discarding them if the file is broken.
+    pub(crate) fn load() -> anyhow::Result<Self> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_str(&xshell::read_file(path)?).unwrap_or_default())
+    }
+
+    pub(crate) fn save(&self) -> anyhow::Result<()> {
+        let path = Self::path()?;
+        xshell::mkdir_p(path.with_file_name(""))?;
+        xshell::write_file(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub(crate) fn is_fresh(&self, manifest_path: &str, bin_name: &str, source_hash: &str) -> bool {
+        matches!(
+            self.0.get(manifest_path).and_then(|bins| bins.get(bin_name)),
+            Some(Checkpoint { source_hash: h, passed: true }) if h == source_hash
+        )
+    }
+
+    pub(crate) fn insert(
+        &mut self,
+        manifest_path: &str,
+        bin_name: &str,
+        source_hash: String,
+        passed: bool,
+    ) {
+        self.0.entry(manifest_path.to_owned()).or_default().insert(
+            bin_name.to_owned(),
+            Checkpoint {
+                source_hash,
+                passed,
+            },
+        );
+    }
+}
+
+/// Times when the tests last passed, keyed with manifest paths relative to the repository, bin
+/// names, and problem URLs.
+///
+/// Unlike [`Checkpoints`], this is meant to be committed to the repository so that it is shared
+/// between machines.
+#[derive(Default, Deserialize, Serialize)]
+pub(crate) struct Timestamps(BTreeMap<String, BTreeMap<String, BTreeMap<String, Timestamp>>>);
+
+#[derive(Deserialize, Serialize)]
+struct Timestamp {
+    source_hash: String,
+    /// Seconds since the Unix epoch.
+    last_passed: u64,
+}
+
+impl Timestamps {
+    pub(crate) fn load(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        serde_json::from_str(&xshell::read_file(path)?)
+            .with_context(|| format!("could not parse `{}`", path.display()))
+    }
+
+    pub(crate) fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            xshell::mkdir_p(parent)?;
+        }
+        xshell::write_file(path, serde_json::to_string_pretty(self)? + "\n")?;
+        Ok(())
+    }
+
+    /// Whether the test passed with the same sources, no more than `max_age` before `now`.
+    pub(crate) fn is_recent(
+        &self,
+        (manifest_path, bin_name, problem): (&str, &str, &str),
+        source_hash: &str,
+        now: Duration,
+        max_age: Option<Duration>,
+    ) -> bool {
+        matches!(
+            self.0
+                .get(manifest_path)
+                .and_then(|bins| bins.get(bin_name))
+                .and_then(|problems| problems.get(problem)),
+            Some(Timestamp { source_hash: h, last_passed })
+                if h == source_hash
+                    && !matches!(
+                        max_age,
+                        Some(max_age)
+                            if now.saturating_sub(Duration::from_secs(*last_passed)) > max_age
+                    )
+        )
+    }
+
+    pub(crate) fn insert(
+        &mut self,
+        (manifest_path, bin_name, problem): (&str, &str, &str),
+        source_hash: String,
+        last_passed: Duration,
+    ) {
+        self.0
+            .entry(manifest_path.to_owned())
+            .or_default()
+            .entry(bin_name.to_owned())
+            .or_default()
+            .insert(
+                problem.to_owned(),
+                Timestamp {
+                    source_hash,
+                    last_passed: last_passed.as_secs(),
+                },
+            );
+    }
+
+    pub(crate) fn remove(&mut self, (manifest_path, bin_name, problem): (&str, &str, &str)) {
+        if let Some(problems) = self
+            .0
+            .get_mut(manifest_path)
+            .and_then(|bins| bins.get_mut(bin_name))
+        {
+            problems.remove(problem);
+        }
+    }
+}
+
+/// Output of `cargo metadata`, valid while the files it was computed from are unmodified.
+#[derive(Deserialize, Serialize)]
+struct MetadataCacheEntry<M> {
+    mtimes: BTreeMap<PathBuf, Option<SystemTime>>,
+    metadata: M,
+}
+
+fn metadata_cache_path(manifest_path: &Path, cargo_flags: &[&str]) -> anyhow::Result<PathBuf> {
+    let mut hasher = Sha256::new();
+    hasher.update(manifest_path.to_string_lossy().as_bytes());
+    for flag in cargo_flags {
+        hasher.update(b"\0");
+        hasher.update(flag.as_bytes());
+    }
+    Ok(cache_dir()?
+        .join("metadata")
+        .join(format!("{:x}.json", hasher.finalize())))
+}
+
+fn mtime(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Records the mtimes of the files `cargo metadata` for `manifest_path` read, and the directories
+/// the targets of the workspace members are discovered from, which are modified when the targets
+/// are added or removed.
+fn input_mtimes(
+    manifest_path: &Path,
+    metadata: &cm::Metadata,
+) -> BTreeMap<PathBuf, Option<SystemTime>> {
+    return metadata
+        .workspace_members
+        .iter()
+        .map(|id| &metadata[id].manifest_path)
+        .chain(&[
+            metadata.workspace_root.join("Cargo.toml"),
+            metadata.workspace_root.join("Cargo.lock"),
+        ])
+        .map(|path| PathBuf::from(path.clone()))
+        .chain(Some(manifest_path.to_owned()))
+        .chain(metadata.workspace_members.iter().flat_map(|id| {
+            let manifest_dir = metadata[id].manifest_path.parent().unwrap();
+            target_dirs(manifest_dir.as_ref())
+        }))
+        .map(|path| {
+            let t = mtime(&path);
+            (path, t)
+        })
+        .collect();
+
+    fn target_dirs(manifest_dir: &Path) -> Vec<PathBuf> {
+        let mut dirs = vec![manifest_dir.to_owned(), manifest_dir.join("src")];
+        for dir in &["src/bin", "examples", "tests", "benches"] {
+            let dir = manifest_dir.join(dir);
+            // Targets may also be `<dir>/<name>/main.rs`.
+            if let Ok(entries) = fs::read_dir(&dir) {
+                dirs.extend(entries.flatten().map(|e| e.path()).filter(|p| p.is_dir()));
+            }
+            dirs.push(dir);
+        }
+        dirs
+    }
+}
+
+fn is_fresh(mtimes: &BTreeMap<PathBuf, Option<SystemTime>>) -> bool {
+    mtimes.iter().all(|(path, t)| mtime(path) == *t)
+}
+
+/// Returns the cached output of `cargo metadata` for `manifest_path`, if the manifests of the
+/// workspace, the `Cargo.lock`, and the directories of the targets are unmodified since then.
+pub(crate) fn load_metadata(
+    manifest_path: &Path,
+    cargo_flags: &[&str],
+) -> anyhow::Result<Option<cm::Metadata>> {
+    let path = metadata_cache_path(manifest_path, cargo_flags)?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    let MetadataCacheEntry::<cm::Metadata> { mtimes, metadata } =
+        match serde_json::from_str(&xshell::read_file(path)?) {
+            Ok(entry) => entry,
+            Err(_) => return Ok(None),
+        };
+    Ok(if is_fresh(&mtimes) {
+        Some(metadata)
+    } else {
+        None
+    })
+}
+
+pub(crate) fn save_metadata(
+    manifest_path: &Path,
+    cargo_flags: &[&str],
+    metadata: &cm::Metadata,
+) -> anyhow::Result<()> {
+    let mtimes = input_mtimes(manifest_path, metadata);
+    let path = metadata_cache_path(manifest_path, cargo_flags)?;
+    xshell::mkdir_p(path.with_file_name(""))?;
+    xshell::write_file(
+        path,
+        serde_json::to_string(&MetadataCacheEntry { mtimes, metadata })?,
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{tempdir_with_files, write_files};
+
+    #[test]
+    fn metadata_is_stale_after_adding_bin() -> anyhow::Result<()> {
+        let tempdir = tempdir_with_files(&[
+            (
+                "Cargo.toml",
+                "[package]\nname = \"a\"\nversion = \"0.0.0\"\nedition = \"2018\"\n",
+            ),
+            ("src/main.rs", "fn main() {}\n"),
+        ])?;
+        let dir = tempdir.path();
+
+        let manifest_path = &dir.join("Cargo.toml");
+        let metadata = crate::workspace::cargo_metadata(manifest_path, &[])?;
+        let mtimes = super::input_mtimes(manifest_path, &metadata);
+        assert!(super::is_fresh(&mtimes));
+
+        write_files(dir, &[("src/bin/b.rs", "fn main() {}\n")])?;
+        assert!(!super::is_fresh(&mtimes));
+        Ok(())
+    }
+}
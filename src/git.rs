@@ -0,0 +1,40 @@
+use anyhow::Context as _;
+use git2::{DiffOptions, Repository, StatusOptions};
+use std::path::PathBuf;
+
+pub(crate) fn dirty_files(repo: &Repository) -> anyhow::Result<Vec<PathBuf>> {
+    let workdir = &dunce::canonicalize(repo.workdir().expect("should not be bare"))?;
+    let statuses = repo.statuses(Some(
+        StatusOptions::new()
+            .include_untracked(true)
+            .recurse_untracked_dirs(true),
+    ))?;
+    Ok(statuses
+        .iter()
+        .filter(|entry| !entry.status().is_ignored())
+        .flat_map(|entry| entry.path().map(|path| workdir.join(path)))
+        .collect())
+}
+
+/// Lists the files that differ between `rev` and the working tree, including the untracked ones.
+pub(crate) fn files_changed_since(repo: &Repository, rev: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let workdir = &dunce::canonicalize(repo.workdir().expect("should not be bare"))?;
+    let tree = repo
+        .revparse_single(rev)
+        .and_then(|object| object.peel_to_tree())
+        .with_context(|| format!("could not resolve `{}`", rev))?;
+    let diff = repo.diff_tree_to_workdir_with_index(
+        Some(&tree),
+        Some(
+            DiffOptions::new()
+                .include_untracked(true)
+                .recurse_untracked_dirs(true),
+        ),
+    )?;
+    Ok(diff
+        .deltas()
+        .flat_map(|delta| vec![delta.old_file().path(), delta.new_file().path()])
+        .flatten()
+        .map(|path| workdir.join(path))
+        .collect())
+}
@@ -1,47 +1,198 @@
-use anyhow::{anyhow, bail, ensure, Context as _};
-use git2::{Branch, BranchType, Oid, Repository};
-use std::borrow::Cow;
+use anyhow::{anyhow, bail, Context as _};
+use camino::Utf8Path;
+use git2::{ErrorCode, Oid, Repository};
+use std::{borrow::Cow, fmt};
 use url::Url;
 
-pub(crate) fn remote(repo: &Repository) -> anyhow::Result<(String, String, String)> {
-    let head = repo.head()?;
-    ensure!(head.is_branch(), "`HEAD` is not a local branch");
-    let local_branch_name = &Branch::wrap(head)
-        .name()?
-        .with_context(|| "the branch name is not a valid UTF-8")?
-        .to_owned();
-    let upstream_name = &repo
-        .find_branch(local_branch_name, BranchType::Local)?
-        .upstream()
-        .and_then(|u| u.name().map(|name| name.unwrap_or_default().to_owned()))
-        .with_context(|| "could not get find the upstream branch")?;
-    let (remote_name, remote_branch_name) = match *upstream_name.split('/').collect::<Vec<_>>() {
-        [remote_name, remote_branch_name] => (remote_name, remote_branch_name.to_owned()),
-        _ => bail!("could not parse {:?}", upstream_name),
+/// Hosting service of the remote repository.
+pub(crate) trait VcsHost: fmt::Debug {
+    /// URL of the repository.
+    fn repo_url(&self) -> Url;
+
+    /// URL of the page showing `path` at `rev`, highlighting `lines` if any.
+    fn blob_url(&self, rev: &str, path: &Utf8Path, lines: Option<(usize, usize)>) -> Url;
+}
+
+#[derive(Debug)]
+pub(crate) struct GitHub {
+    repo_url: Url,
+}
+
+impl VcsHost for GitHub {
+    fn repo_url(&self) -> Url {
+        self.repo_url.clone()
+    }
+
+    fn blob_url(&self, rev: &str, path: &Utf8Path, lines: Option<(usize, usize)>) -> Url {
+        let mut url = blob_url(&self.repo_url, &["blob"], rev, path);
+        if let Some((start, end)) = lines {
+            url.set_fragment(Some(&format!("L{}-L{}", start, end)));
+        }
+        url
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct GitLab {
+    repo_url: Url,
+}
+
+impl VcsHost for GitLab {
+    fn repo_url(&self) -> Url {
+        self.repo_url.clone()
+    }
+
+    fn blob_url(&self, rev: &str, path: &Utf8Path, lines: Option<(usize, usize)>) -> Url {
+        let mut url = blob_url(&self.repo_url, &["-", "blob"], rev, path);
+        if let Some((start, end)) = lines {
+            url.set_fragment(Some(&format!("L{}-{}", start, end)));
+        }
+        url
+    }
+}
+
+/// Appends `segments`, `rev`, and `path` to `repo_url`.
+fn blob_url(repo_url: &Url, segments: &[&str], rev: &str, path: &Utf8Path) -> Url {
+    let mut url = repo_url.clone();
+    url.path_segments_mut()
+        .expect("this is `https://`")
+        .extend(segments)
+        .push(rev)
+        .extend(path);
+    url
+}
+
+#[derive(Debug)]
+pub(crate) struct Remote {
+    pub(crate) host: Box<dyn VcsHost>,
+}
+
+/// Reads the remote named `remote_name`.
+///
+/// If `remote_name` is `None`, the remote of the upstream branch is used, falling back to `origin`.
+/// `github_host` is the domain treated as GitHub, defaulting to `github.com`.
+pub(crate) fn remote(
+    repo: &Repository,
+    remote_name: Option<&str>,
+    github_host: Option<&str>,
+) -> anyhow::Result<Remote> {
+    let remote_name = match remote_name {
+        Some(remote_name) => remote_name.to_owned(),
+        None => upstream_remote(repo)?.unwrap_or_else(|| "origin".to_owned()),
     };
     let remote_url = repo
-        .find_remote(remote_name)
-        .with_context(|| format!("`{}` is not a remote", upstream_name))?
+        .find_remote(&remote_name)
+        .with_context(|| format!("`{}` is not a remote", remote_name))?
         .url()
-        .and_then(|url| url.parse::<Url>().ok())
+        .and_then(parse_remote_url)
         .with_context(|| "the remote URL is not a valid URL")?;
-    ensure!(
-        remote_url.host_str() == Some("github.com"),
-        "expected GitHub, got `{}`, remote_url",
-    );
-    let (s1, s2) = match *remote_url.path().split('/').collect::<Vec<_>>() {
-        [_, s1, s2] => (s1, s2),
-        _ => bail!("expected 2 segments: `{}`", remote_url.path()),
+    let domain = remote_url
+        .host_str()
+        .with_context(|| format!("the remote URL has no host: {}", remote_url))?;
+    let new_host: fn(Url) -> Box<dyn VcsHost> = if domain == github_host.unwrap_or("github.com") {
+        |repo_url| Box::new(GitHub { repo_url })
+    } else if domain == "gitlab.com" {
+        |repo_url| Box::new(GitLab { repo_url })
+    } else {
+        bail!(
+            "expected GitHub or GitLab, got `{}`. if `{}` is a GitHub Enterprise instance, pass \
+             `--github-host {}`",
+            remote_url,
+            domain,
+            domain,
+        );
+    };
+    let segments = remote_url
+        .path()
+        .trim_matches('/')
+        .split('/')
+        .collect::<Vec<_>>();
+    // The owner, or `/`-separated namespace path such as `group/subgroup` for GitLab.
+    let (username, repo_name) = match &*segments {
+        [namespace @ .., repo_name] if !namespace.is_empty() => (
+            namespace
+                .iter()
+                .map(|segment| percent_decode(segment))
+                .collect::<anyhow::Result<Vec<_>>>()?
+                .join("/"),
+            percent_decode(repo_name.trim_end_matches(".git"))?,
+        ),
+        _ => bail!("expected 2 or more segments: `{}`", remote_url.path()),
     };
-    let username = s1.to_owned();
-    let repo_name = s2.trim_end_matches(".git").to_owned();
-    Ok((username, repo_name, remote_branch_name))
+    let repo_url = format!("https://{}", domain);
+    let mut repo_url = repo_url
+        .parse::<Url>()
+        .with_context(|| format!("invalid URL: {}", repo_url))?;
+    // The owner and the repository name are decoded, so they are re-encoded here.
+    repo_url
+        .path_segments_mut()
+        .expect("this is `https://`")
+        .extend(username.split('/'))
+        .push(&repo_name);
+    Ok(Remote {
+        host: new_host(repo_url),
+    })
+}
+
+/// Name of the remote of the upstream branch, if `HEAD` is a branch with an upstream.
+fn upstream_remote(repo: &Repository) -> anyhow::Result<Option<String>> {
+    let head = repo.head()?;
+    if !head.is_branch() {
+        return Ok(None);
+    }
+    let refname = head
+        .name()
+        .with_context(|| "the branch name is not a valid UTF-8")?;
+    match repo.branch_upstream_remote(refname) {
+        Ok(remote_name) => Ok(Some(
+            remote_name
+                .as_str()
+                .with_context(|| "the remote name is not a valid UTF-8")?
+                .to_owned(),
+        )),
+        Err(err) if err.code() == ErrorCode::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Parses a remote URL, accepting the SCP-like syntax (`git@github.com:user/repo.git`) as well.
+fn parse_remote_url(url: &str) -> Option<Url> {
+    match url.split_once(':') {
+        Some((user_and_host, path)) if !url.contains("://") && !user_and_host.contains('/') => {
+            format!("ssh://{}/{}", user_and_host, path.trim_start_matches('/'))
+                .parse()
+                .ok()
+        }
+        _ => url.parse().ok(),
+    }
 }
 
 pub(crate) fn rev(repo: &Repository) -> anyhow::Result<Oid> {
     Ok(repo.head()?.peel_to_commit()?.id())
 }
 
+/// Resolves `spec` to a commit for the links. The name of a branch or a tag is kept, and the full
+/// commit ID is returned for anything else.
+pub(crate) fn resolve_rev(repo: &Repository, spec: &str) -> anyhow::Result<String> {
+    let (object, reference) = repo
+        .revparse_ext(spec)
+        .with_context(|| format!("could not resolve `{}`", spec))?;
+    let commit = object
+        .peel_to_commit()
+        .with_context(|| format!("`{}` is not a commit", spec))?;
+    // `HEAD` is resolved to the branch, so the name is compared.
+    if let Some(reference) = reference {
+        if (reference.is_branch() || reference.is_tag())
+            && [reference.name(), reference.shorthand()].contains(&Some(spec))
+        {
+            if let Some(shorthand) = reference.shorthand() {
+                return Ok(shorthand.to_owned());
+            }
+        }
+    }
+    Ok(commit.id().to_string())
+}
+
 fn percent_decode(segment: &str) -> anyhow::Result<String> {
     let decodor = || percent_encoding::percent_decode_str(segment);
     decodor()
@@ -50,24 +201,148 @@ fn percent_decode(segment: &str) -> anyhow::Result<String> {
         .map_err(|e| anyhow!("{}: {}", e, decodor().decode_utf8_lossy()))
 }
 
-fn second_path_segment(url: &Url) -> anyhow::Result<String> {
-    let segments = url
-        .path_segments()
-        .map(|ss| ss.collect::<Vec<_>>())
-        .unwrap_or_default();
+#[cfg(test)]
+mod tests {
+    use super::{remote, resolve_rev, rev};
+    use git2::{Repository, Signature};
+    use tempfile::TempDir;
 
-    let segment = segments.get(1).with_context(|| {
-        format!(
-            "the number of path segments is {} but the index is 1: {}",
-            segments.len(),
-            url,
-        )
-    })?;
+    /// Creates a repository with an empty commit on `branch`.
+    fn init(branch: &str) -> anyhow::Result<(TempDir, Repository)> {
+        let tempdir = crate::testing::tempdir()?;
+        let repo = Repository::init(tempdir.path())?;
+        {
+            let tree = repo.find_tree(repo.index()?.write_tree()?)?;
+            let sig = Signature::now("a", "a@example.com")?;
+            let refname = format!("refs/heads/{}", branch);
+            repo.commit(Some(&refname), &sig, &sig, "a", &tree, &[])?;
+            repo.set_head(&refname)?;
+        }
+        Ok((tempdir, repo))
+    }
 
-    let decodor = || percent_encoding::percent_decode_str(segment);
+    /// Reads the repository URL from a remote `origin` with `url`.
+    fn origin_repo_url(url: &str) -> anyhow::Result<String> {
+        let (_tempdir, repo) = init("master")?;
+        repo.remote("origin", url)?;
+        Ok(remote(&repo, None, None)?.host.repo_url().to_string())
+    }
 
-    decodor()
-        .decode_utf8()
-        .map(Cow::into_owned)
-        .map_err(|e| anyhow!("{}: {}", e, decodor().decode_utf8_lossy()))
+    #[test]
+    fn remote_url_forms() -> anyhow::Result<()> {
+        for url in &[
+            "git@github.com:foo/bar.git",
+            "ssh://git@github.com/foo/bar.git",
+            "https://github.com/foo/bar",
+            "https://github.com/foo/bar.git",
+        ] {
+            assert_eq!(
+                "https://github.com/foo/bar",
+                origin_repo_url(url)?,
+                "{}",
+                url
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn remote_from_upstream_with_slashes() -> anyhow::Result<()> {
+        let (_tempdir, repo) = init("feature/x")?;
+        repo.remote("origin", "https://github.com/a/origin")?;
+        repo.remote("upstream", "https://github.com/a/upstream")?;
+        let mut config = repo.config()?;
+        config.set_str("branch.feature/x.remote", "upstream")?;
+        config.set_str("branch.feature/x.merge", "refs/heads/feature/x")?;
+
+        let host = remote(&repo, None, None)?.host;
+        assert_eq!("https://github.com/a/upstream", host.repo_url().as_str());
+        let host = remote(&repo, Some("origin"), None)?.host;
+        assert_eq!("https://github.com/a/origin", host.repo_url().as_str());
+        Ok(())
+    }
+
+    #[test]
+    fn remote_on_unknown_host() {
+        let err = origin_repo_url("https://git.example.com/a/b")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("`https://git.example.com/a/b`"), "{}", err);
+        assert!(err.contains("--github-host git.example.com"), "{}", err);
+    }
+
+    #[test]
+    fn remote_with_encoded_owner() -> anyhow::Result<()> {
+        assert_eq!(
+            "https://github.com/foo/bar",
+            origin_repo_url("https://github.com/f%6Fo/bar")?,
+        );
+        // Decoded and then re-encoded.
+        assert_eq!(
+            "https://github.com/a%20b/bar",
+            origin_repo_url("https://github.com/a%20b/bar")?,
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn remote_with_namespace() -> anyhow::Result<()> {
+        let (_tempdir, repo) = init("master")?;
+        repo.remote("two", "https://gitlab.com/group/repo.git")?;
+        repo.remote("three", "https://gitlab.com/group/subgroup/repo.git")?;
+
+        let host = remote(&repo, Some("two"), None)?.host;
+        assert_eq!("https://gitlab.com/group/repo", host.repo_url().as_str());
+        let host = remote(&repo, Some("three"), None)?.host;
+        assert_eq!(
+            "https://gitlab.com/group/subgroup/repo",
+            host.repo_url().as_str(),
+        );
+        assert_eq!(
+            "https://gitlab.com/group/subgroup/repo/-/blob/abc/src/lib.rs#L1-2",
+            host.blob_url("abc", "src/lib.rs".as_ref(), Some((1, 2)))
+                .as_str(),
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn remote_with_detached_head() -> anyhow::Result<()> {
+        let (_tempdir, repo) = init("master")?;
+        repo.remote("origin", "https://github.com/a/origin")?;
+        let oid = repo.head()?.peel_to_commit()?.id();
+        repo.set_head_detached(oid)?;
+
+        let host = remote(&repo, None, None)?.host;
+        assert_eq!("https://github.com/a/origin", host.repo_url().as_str());
+        assert_eq!(oid, rev(&repo)?);
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_rev_keeps_branches_and_tags() -> anyhow::Result<()> {
+        let (_tempdir, repo) = init("master")?;
+        let commit = repo.head()?.peel_to_commit()?;
+        let oid = commit.id().to_string();
+        repo.tag_lightweight("v1", commit.as_object(), false)?;
+
+        assert_eq!("master", resolve_rev(&repo, "master")?);
+        assert_eq!("master", resolve_rev(&repo, "refs/heads/master")?);
+        assert_eq!("v1", resolve_rev(&repo, "v1")?);
+        assert_eq!(oid, resolve_rev(&repo, "HEAD")?);
+        assert_eq!(oid, resolve_rev(&repo, &oid[..7])?);
+        assert!(resolve_rev(&repo, "master^{tree}").is_err());
+        assert!(resolve_rev(&repo, "nonexistent").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn remote_without_upstream() -> anyhow::Result<()> {
+        let (_tempdir, repo) = init("master")?;
+        repo.remote("origin", "https://github.com/a/origin")?;
+
+        let host = remote(&repo, None, None)?.host;
+        assert_eq!("https://github.com/a/origin", host.repo_url().as_str());
+        Ok(())
+    }
 }
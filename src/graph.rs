@@ -0,0 +1,90 @@
+use crate::{
+    shell::Shell,
+    workspace::{self, normal_deps, PackageExt as _},
+};
+use cargo_metadata as cm;
+use git2::Repository;
+use maplit::btreeset;
+use std::{path::Path, str::FromStr};
+
+/// Format of `cargo cpl graph`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    Dot,
+}
+
+impl GraphFormat {
+    pub const VARIANTS: &'static [&'static str] = &["dot"];
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dot" => Ok(Self::Dot),
+            s => Err(format!("expected one of {:?}, got {:?}", Self::VARIANTS, s)),
+        }
+    }
+}
+
+/// Prints the libraries in the repository and the normal dependencies between them.
+///
+/// Libraries used by any bin with a problem URL are filled with green. Unlike `verify`, unused
+/// dependencies are not excluded and the tests are not run.
+pub fn graph(format: GraphFormat, cwd: &Path, shell: &mut Shell) -> anyhow::Result<()> {
+    let repo = &Repository::discover(cwd)?;
+    let repo_workdir = repo.workdir().expect("this is constructed with `discover`");
+
+    let metadata_list = workspace::list_metadata(repo_workdir, &[], true, None)?;
+
+    let is_lib = |package: &cm::Package| {
+        package.lib_target().is_some() || package.proc_macro_target().is_some()
+    };
+
+    let mut libs = btreeset!();
+    let mut edges = btreeset!();
+    let mut verified = btreeset!();
+    for (ws_member, metadata) in &metadata_list {
+        let ws_member = &metadata[ws_member];
+        let normal_deps = &normal_deps(metadata);
+        let deps_in_same_repo = normal_deps[&ws_member.id]
+            .iter()
+            .map(|&(_, pkg)| pkg)
+            .filter(|pkg| metadata_list.contains_key(*pkg) && is_lib(&metadata[pkg]))
+            .collect::<Vec<_>>();
+
+        if is_lib(ws_member) {
+            libs.insert(&*ws_member.name);
+            for dep in &deps_in_same_repo {
+                edges.insert((&*ws_member.name, &*metadata[dep].name));
+            }
+        }
+        if !ws_member.bin_problems(shell)?.is_empty() {
+            verified.extend(
+                workspace::transitive_normal_deps(normal_deps, deps_in_same_repo)
+                    .into_iter()
+                    .filter(|pkg| metadata_list.contains_key(*pkg))
+                    .map(|pkg| &*metadata[pkg].name),
+            );
+        }
+    }
+
+    match format {
+        GraphFormat::Dot => {
+            writeln!(shell.out(), "digraph {{")?;
+            for lib in &libs {
+                write!(shell.out(), "    {:?}", lib)?;
+                if verified.contains(lib) {
+                    write!(shell.out(), " [style=filled, fillcolor=palegreen]")?;
+                }
+                writeln!(shell.out(), ";")?;
+            }
+            for (from, to) in &edges {
+                writeln!(shell.out(), "    {:?} -> {:?};", from, to)?;
+            }
+            writeln!(shell.out(), "}}")?;
+        }
+    }
+    Ok(())
+}
@@ -1,8 +1,22 @@
+mod bundle;
+mod cache;
+mod git;
 mod github;
+mod graph;
+mod list;
 mod process_builder;
 mod rust;
 mod shell;
+#[cfg(test)]
+mod testing;
 mod verify;
 mod workspace;
 
-pub use crate::{shell::Shell, verify::verify_for_gh_pages};
+pub use crate::{
+    bundle::{bundle, expand, expand_mods},
+    cache::clean,
+    graph::{graph, GraphFormat},
+    list::list,
+    shell::{ColorChoice, OutputFormat, Shell, Verbosity},
+    verify::{run_as_rustdoc, verify_for_gh_pages, CopyMode, TocSort, VerifyForGhPagesOptions},
+};
@@ -0,0 +1,70 @@
+use crate::{
+    shell::{OutputFormat, Shell},
+    workspace::{self, PackageExt as _, TargetExt as _},
+};
+use git2::Repository;
+use serde::Serialize;
+use std::{collections::BTreeMap, path::Path};
+use url::Url;
+
+/// Prints the packages in the repository with their libraries and the problems of their bins,
+/// without building or testing anything.
+pub fn list(format: OutputFormat, cwd: &Path, shell: &mut Shell) -> anyhow::Result<()> {
+    let repo = &Repository::discover(cwd)?;
+    let repo_workdir = repo.workdir().expect("this is constructed with `discover`");
+
+    let metadata_list = workspace::list_metadata(repo_workdir, &[], true, None)?;
+
+    let packages = metadata_list
+        .iter()
+        .map(|(ws_member, metadata)| {
+            let ws_member = &metadata[ws_member];
+            let lib = ws_member
+                .lib_target()
+                .or_else(|| ws_member.proc_macro_target())
+                .map(|t| t.crate_name());
+            let bins = ws_member.bin_problems(shell)?.into_iter().collect();
+            Ok(ListPackage {
+                name: &ws_member.name,
+                manifest_path: ws_member.manifest_path.as_str(),
+                lib,
+                bins,
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    match format {
+        OutputFormat::Human => {
+            for ListPackage {
+                name,
+                manifest_path,
+                lib,
+                bins,
+            } in &packages
+            {
+                writeln!(shell.out(), "{} ({})", name, manifest_path)?;
+                if let Some(lib) = lib {
+                    writeln!(shell.out(), "    lib: {}", lib)?;
+                }
+                for (bin, problem) in bins {
+                    writeln!(shell.out(), "    bin: {} ({})", bin, problem)?;
+                }
+            }
+        }
+        OutputFormat::Json => {
+            writeln!(shell.out(), "{}", serde_json::to_string(&packages)?)?;
+        }
+    }
+    Ok(())
+}
+
+/// Element of the output of `cargo cpl list --format json`.
+#[derive(Serialize)]
+struct ListPackage<'a> {
+    name: &'a str,
+    manifest_path: &'a str,
+    /// Crate name of the `lib` or `proc-macro` target.
+    lib: Option<String>,
+    /// Problem URLs of the bin targets.
+    bins: BTreeMap<String, Url>,
+}
@@ -1,11 +1,15 @@
 use anyhow::Context as _;
-use cargo_cpl::Shell;
-use std::{env, process};
+use cargo_cpl::{
+    ColorChoice, CopyMode, GraphFormat, OutputFormat, Shell, TocSort, Verbosity,
+    VerifyForGhPagesOptions,
+};
+use std::{env, io, path::PathBuf, process, time::Duration};
 use structopt::{
     clap::{self, AppSettings},
     StructOpt,
 };
 use termcolor::{Color, ColorSpec, WriteColor};
+use url::Url;
 
 #[derive(Debug, StructOpt)]
 #[structopt(
@@ -16,12 +20,103 @@ use termcolor::{Color, ColorSpec, WriteColor};
 )]
 enum Opt {
     #[structopt(about, author)]
-    Cpl(OptCpl),
+    Cpl {
+        /// Coloring
+        #[structopt(
+            long,
+            value_name("WHEN"),
+            default_value("auto"),
+            possible_values(ColorChoice::VARIANTS)
+        )]
+        color: ColorChoice,
+
+        /// Print only warnings and errors
+        #[structopt(short, long, conflicts_with("verbose"))]
+        quiet: bool,
+
+        /// Print each copied file. Pass twice to also print the commands run silently
+        #[structopt(short, long, parse(from_occurrences))]
+        verbose: u8,
+
+        #[structopt(subcommand)]
+        cmd: OptCpl,
+    },
 }
 
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, StructOpt)]
 enum OptCpl {
     Verify(OptCplVerify),
+
+    /// List the packages with their libraries and the problems of their bins
+    List {
+        /// Format of the output
+        #[structopt(
+            long,
+            value_name("FORMAT"),
+            default_value("human"),
+            possible_values(OutputFormat::VARIANTS)
+        )]
+        format: OutputFormat,
+    },
+
+    /// Print the dependencies between the libraries
+    Graph {
+        /// Format of the output
+        #[structopt(
+            long,
+            value_name("FORMAT"),
+            default_value("dot"),
+            possible_values(GraphFormat::VARIANTS)
+        )]
+        format: GraphFormat,
+    },
+
+    /// Remove the cache directory
+    Clean {
+        /// Print the paths to remove without removing them
+        #[structopt(long)]
+        dry_run: bool,
+    },
+
+    /// Print the bin target with the path dependencies inlined, for submission
+    Bundle {
+        /// Package containing the bin target
+        #[structopt(short, long, value_name("SPEC"))]
+        package: Option<String>,
+
+        /// Number of spaces to indent the inlined modules with per level
+        #[structopt(long, value_name("N"), default_value("4"))]
+        indent_width: usize,
+
+        /// Do not re-indent the inlined modules, keeping the original bytes
+        #[structopt(long, conflicts_with("indent-width"))]
+        no_indent: bool,
+
+        /// Name of the bin target
+        bin: String,
+    },
+
+    /// Print the bin target or the lib target with the file modules inlined
+    Expand {
+        /// Package containing the target
+        #[structopt(short, long, value_name("SPEC"))]
+        package: Option<String>,
+
+        /// Remove the comments
+        #[structopt(long)]
+        strip_comments: bool,
+
+        /// Name of the bin target [default: the lib target]
+        bin: Option<String>,
+    },
+
+    /// Print a completion script to stdout
+    Completions {
+        /// Shell
+        #[structopt(possible_values(&clap::Shell::variants()))]
+        shell: clap::Shell,
+    },
 }
 
 #[derive(Debug, StructOpt)]
@@ -31,20 +126,335 @@ enum OptCplVerify {
         #[structopt(long)]
         open: bool,
 
-        /// `nightly` toolchain
-        #[structopt(long, value_name("TOOLCHAIN"), default_value("nightly"))]
-        toolchain: String,
+        /// Open the docs of the crate instead of the table of contents
+        #[structopt(long, value_name("NAME"))]
+        open_crate: Option<String>,
+
+        /// `nightly` toolchain [default: `toolchain` in `[workspace.metadata.cargo-cpl]`, the channel in `rust-toolchain.toml`, or `nightly`]
+        #[structopt(long, value_name("TOOLCHAIN"))]
+        toolchain: Option<String>,
+
+        /// Only verify packages with uncommitted changes and the packages depending on them
+        #[structopt(long)]
+        dirty: bool,
+
+        /// Only verify packages changed since the revision and the packages depending on them
+        #[structopt(long, value_name("REV"))]
+        changed_since: Option<String>,
+
+        /// Rebuild the docs whenever files in the repository change, without running the tests
+        #[structopt(long)]
+        watch: bool,
+
+        /// Show the solutions using each library as examples
+        #[structopt(long)]
+        show_examples: bool,
+
+        /// Also list the dev-dependencies and the build-dependencies
+        #[structopt(long)]
+        show_dev_deps: bool,
+
+        /// Git remote to link to [default: the remote of the upstream branch, or `origin`]
+        #[structopt(long, value_name("NAME"))]
+        remote: Option<String>,
+
+        /// Domain of the GitHub (Enterprise) instance [default: github.com]
+        #[structopt(long, value_name("DOMAIN"))]
+        github_host: Option<String>,
+
+        /// Revision to link the source files with [default: the commit of `HEAD`]
+        #[structopt(long, value_name("REV"))]
+        rev: Option<String>,
+
+        /// Format of the report printed to stdout
+        #[structopt(
+            long,
+            value_name("FORMAT"),
+            default_value("human"),
+            possible_values(OutputFormat::VARIANTS)
+        )]
+        format: OutputFormat,
+
+        /// Number of tests and `cargo metadata` to run in parallel [default: the number of CPUs]
+        #[structopt(short, long, value_name("N"))]
+        jobs: Option<usize>,
+
+        /// Run all of the tests even if some of them fail
+        #[structopt(long)]
+        keep_going: bool,
+
+        /// Number of times to re-run a failed test, for transient failures of the judges
+        #[structopt(long, value_name("N"), default_value("0"))]
+        retries: u32,
+
+        /// Kill a test if it does not finish in this many seconds, treating it as failed
+        #[structopt(long, value_name("SECONDS"))]
+        test_timeout: Option<u64>,
+
+        /// Run the tests even if they passed with the same sources before
+        #[structopt(long)]
+        force: bool,
+
+        /// Skip `cargo compete t`. The checkmarks only mean that the libraries have verification binaries
+        #[structopt(long)]
+        no_test: bool,
+
+        /// Skip `cargo udeps`, treating all of the dependencies as used
+        #[structopt(long)]
+        skip_udeps: bool,
+
+        /// Print what would be tested and documented without running `cargo udeps`, the tests, and `cargo doc`
+        #[structopt(long)]
+        dry_run: bool,
+
+        /// Verify only the specified packages
+        #[structopt(short, long("package"), value_name("SPEC"), number_of_values(1))]
+        packages: Vec<String>,
+
+        /// Directory to build the docs in [default: the cache directory]
+        #[structopt(long, value_name("PATH"))]
+        out_dir: Option<PathBuf>,
+
+        /// Run without accessing the network
+        #[structopt(long)]
+        offline: bool,
+
+        /// Require Cargo.lock to be up to date, including the one of the workspace for the docs generated in a previous run
+        #[structopt(long)]
+        locked: bool,
+
+        /// Require Cargo.lock to be up to date, and run without accessing the network
+        #[structopt(long)]
+        frozen: bool,
+
+        /// Run `cargo metadata` even if the manifests are unmodified
+        #[structopt(long)]
+        no_metadata_cache: bool,
+
+        /// Write `verification-summary.json` to the doc directory
+        #[structopt(long)]
+        emit_summary: bool,
+
+        /// Base URL of the docs of the crates.io crates [default: https://docs.rs/]
+        #[structopt(long, value_name("URL"))]
+        docs_base_url: Option<Url>,
+
+        /// Embed the icons in the table of contents instead of linking to GitHub
+        #[structopt(long)]
+        offline_assets: bool,
+
+        /// Order of the crates in each folder of the table of contents
+        #[structopt(
+            long,
+            value_name("ORDER"),
+            default_value("name"),
+            possible_values(TocSort::VARIANTS)
+        )]
+        toc_sort: TocSort,
+
+        /// Maximum depth of the folders in the table of contents [default: unlimited]
+        #[structopt(long, value_name("N"))]
+        toc_depth: Option<usize>,
+
+        /// Exit with an error if any library has no verifications
+        #[structopt(long)]
+        deny_unverified: bool,
+
+        /// Only warn instead of failing when the path dependencies in the repository form a cycle
+        #[structopt(long)]
+        allow_dep_cycles: bool,
+
+        /// Do not copy the files matching the glob to the workspace for the docs, in addition to `target` and `.git`
+        #[structopt(long, value_name("GLOB"), number_of_values(1))]
+        copy_exclude: Vec<String>,
+
+        /// Copy the whole repository for the docs, instead of only the libraries and their path dependencies
+        #[structopt(long)]
+        copy_whole_repo: bool,
+
+        /// How to copy the files for the docs
+        #[structopt(
+            long,
+            value_name("MODE"),
+            default_value("copy"),
+            possible_values(CopyMode::VARIANTS)
+        )]
+        copy_mode: CopyMode,
+
+        /// Warn about the path dependencies in the repository reported unused by `cargo udeps`
+        #[structopt(long)]
+        warn_unused: bool,
+
+        /// Only verify the workspace of the manifest, instead of all of the workspaces in the repository
+        #[structopt(long, value_name("PATH"))]
+        manifest_path: Option<PathBuf>,
+
+        /// Only verify the workspace containing the current directory
+        #[structopt(long, conflicts_with("manifest-path"))]
+        current_workspace: bool,
+
+        /// Warn about the problem URLs not reachable with `curl --head`, before the tests
+        #[structopt(long, conflicts_with_all(&["offline", "frozen"]))]
+        check_problem_urls: bool,
+
+        /// Write `competitive-verifier-result.json` (the `result.json` format of competitive-verifier 2.x) to the doc directory
+        #[structopt(long)]
+        emit_cverifier: bool,
+
+        /// Record when each test passed in this JSON file, and skip the tests passed with the same sources. Commit it to share it between machines
+        #[structopt(long, value_name("PATH"))]
+        timestamps: Option<PathBuf>,
+
+        /// Re-run the tests recorded in `--timestamps` as passed more than this many seconds ago
+        #[structopt(long, value_name("SECONDS"), requires("timestamps"))]
+        max_age: Option<u64>,
     },
 }
 
 fn main() {
-    let Opt::Cpl(opt) = &Opt::from_args();
+    // `verify gh-pages` runs this executable as `rustdoc`.
+    match cargo_cpl::run_as_rustdoc() {
+        Ok(Some(code)) => process::exit(code),
+        Ok(None) => {}
+        Err(err) => exit_with_error(err, Shell::new().err()),
+    }
+
+    let Opt::Cpl {
+        color,
+        quiet,
+        verbose,
+        cmd: opt,
+    } = &Opt::from_args();
     let shell = &mut Shell::new();
+    shell.set_color_choice(*color);
+    shell.set_verbosity(match (quiet, verbose) {
+        (true, _) => Verbosity::Quiet,
+        (false, 0) => Verbosity::Normal,
+        (false, 1) => Verbosity::Verbose,
+        (false, _) => Verbosity::VeryVerbose,
+    });
     let result = (|| {
         let cwd = &env::current_dir().with_context(|| "could not get the CWD")?;
         match opt {
-            OptCpl::Verify(OptCplVerify::GhPages { open, toolchain }) => {
-                cargo_cpl::verify_for_gh_pages(toolchain, *open, cwd, shell)
+            OptCpl::Verify(OptCplVerify::GhPages {
+                open,
+                open_crate,
+                toolchain,
+                dirty,
+                changed_since,
+                watch,
+                show_examples,
+                show_dev_deps,
+                remote,
+                github_host,
+                rev,
+                format,
+                jobs,
+                keep_going,
+                retries,
+                test_timeout,
+                force,
+                no_test,
+                skip_udeps,
+                dry_run,
+                packages,
+                out_dir,
+                offline,
+                locked,
+                frozen,
+                no_metadata_cache,
+                emit_summary,
+                docs_base_url,
+                offline_assets,
+                toc_sort,
+                toc_depth,
+                deny_unverified,
+                allow_dep_cycles,
+                copy_exclude,
+                copy_whole_repo,
+                copy_mode,
+                warn_unused,
+                manifest_path,
+                current_workspace,
+                check_problem_urls,
+                emit_cverifier,
+                timestamps,
+                max_age,
+            }) => cargo_cpl::verify_for_gh_pages(
+                VerifyForGhPagesOptions {
+                    nightly_toolchain: toolchain.as_deref(),
+                    open: *open,
+                    open_crate: open_crate.as_deref(),
+                    dirty: *dirty,
+                    watch: *watch,
+                    show_examples: *show_examples,
+                    show_dev_deps: *show_dev_deps,
+                    remote: remote.as_deref(),
+                    github_host: github_host.as_deref(),
+                    rev: rev.as_deref(),
+                    format: *format,
+                    jobs: *jobs,
+                    keep_going: *keep_going,
+                    force: *force,
+                    no_test: *no_test,
+                    skip_udeps: *skip_udeps,
+                    packages,
+                    out_dir: out_dir.as_deref(),
+                    offline: *offline,
+                    locked: *locked,
+                    frozen: *frozen,
+                    no_metadata_cache: *no_metadata_cache,
+                    emit_summary: *emit_summary,
+                    docs_base_url: docs_base_url.as_ref(),
+                    retries: *retries,
+                    test_timeout: test_timeout.map(Duration::from_secs),
+                    dry_run: *dry_run,
+                    changed_since: changed_since.as_deref(),
+                    offline_assets: *offline_assets,
+                    toc_sort: *toc_sort,
+                    toc_depth: *toc_depth,
+                    deny_unverified: *deny_unverified,
+                    allow_dep_cycles: *allow_dep_cycles,
+                    copy_exclude,
+                    copy_whole_repo: *copy_whole_repo,
+                    copy_mode: *copy_mode,
+                    warn_unused: *warn_unused,
+                    manifest_path: manifest_path.as_deref(),
+                    current_workspace: *current_workspace,
+                    check_problem_urls: *check_problem_urls,
+                    emit_cverifier: *emit_cverifier,
+                    timestamps: timestamps.as_deref(),
+                    max_age: max_age.map(Duration::from_secs),
+                },
+                cwd,
+                shell,
+            ),
+            OptCpl::List { format } => cargo_cpl::list(*format, cwd, shell),
+            OptCpl::Graph { format } => cargo_cpl::graph(*format, cwd, shell),
+            OptCpl::Clean { dry_run } => cargo_cpl::clean(*dry_run, shell),
+            OptCpl::Bundle {
+                package,
+                indent_width,
+                no_indent,
+                bin,
+            } => {
+                let indent_width = if *no_indent { 0 } else { *indent_width };
+                cargo_cpl::bundle(bin, package.as_deref(), indent_width, cwd, shell)
+            }
+            OptCpl::Expand {
+                package,
+                strip_comments,
+                bin,
+            } => cargo_cpl::expand(
+                bin.as_deref(),
+                package.as_deref(),
+                *strip_comments,
+                cwd,
+                shell,
+            ),
+            OptCpl::Completions { shell: kind } => {
+                Opt::clap().gen_completions_to("cargo-cpl", *kind, &mut io::stdout());
+                Ok(())
             }
         }
     })();
@@ -1,17 +1,27 @@
 use anyhow::{anyhow, bail, Context as _};
 use itertools::Itertools as _;
-use maplit::btreemap;
+use maplit::{btreemap, btreeset};
+use once_cell::sync::OnceCell;
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet},
     env,
     ffi::{OsStr, OsString},
     fmt,
+    io::{self, BufRead as _, BufReader, Read, Write},
+    ops::{Deref, DerefMut},
     path::{Path, PathBuf},
-    process::{ExitStatus, Output, Stdio},
+    process::{self, Child, ExitStatus, Output, Stdio},
     str,
+    sync::{
+        atomic::{self, AtomicBool, AtomicUsize},
+        mpsc::{self, RecvTimeoutError},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
-use crate::shell::Shell;
+use crate::shell::{Shell, Verbosity};
 
 pub(crate) fn process(program: impl AsRef<OsStr>) -> ProcessBuilder<NotPresent> {
     ProcessBuilder {
@@ -19,6 +29,116 @@ pub(crate) fn process(program: impl AsRef<OsStr>) -> ProcessBuilder<NotPresent>
         args: vec![],
         cwd: (),
         env: btreemap!(),
+        env_remove: btreeset!(),
+        timeout: None,
+    }
+}
+
+/// `cargo` of `toolchain`, run with `rustup run` or, if `rustup` is not installed, with
+/// `cargo +<toolchain>`.
+pub(crate) fn toolchain_cargo(toolchain: &str) -> ProcessBuilder<NotPresent> {
+    toolchain_proxy(toolchain, "cargo")
+}
+
+/// `rustfmt` of `toolchain`, run in the same way as [`toolchain_cargo`].
+pub(crate) fn toolchain_rustfmt(toolchain: &str) -> ProcessBuilder<NotPresent> {
+    toolchain_proxy(toolchain, "rustfmt")
+}
+
+fn toolchain_proxy(toolchain: &str, program: &str) -> ProcessBuilder<NotPresent> {
+    if rustup_installed() {
+        process("rustup").args(&["run", toolchain, program])
+    } else {
+        process(program).arg(format!("+{}", toolchain))
+    }
+}
+
+/// Whether `rustup` is in the `$PATH`.
+pub(crate) fn rustup_installed() -> bool {
+    let file_name = format!("rustup{}", env::consts::EXE_SUFFIX);
+    let paths = env::var_os("PATH").unwrap_or_default();
+    env::split_paths(&paths).any(|dir| dir.join(&file_name).is_file())
+}
+
+/// Whether Ctrl-C has been pressed.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Number of the live [`CtrlcGuard`]s.
+static CTRLC_WATCHERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Installs the Ctrl-C handler, and returns a guard telling it that the caller checks
+/// [`interrupted`] until the guard is dropped.
+///
+/// While no guard is alive, Ctrl-C exits the process immediately as usual.
+pub(crate) fn watch_ctrlc() -> anyhow::Result<CtrlcGuard> {
+    static HANDLER: OnceCell<()> = OnceCell::new();
+    HANDLER.get_or_try_init(|| {
+        ctrlc::set_handler(|| {
+            INTERRUPTED.store(true, atomic::Ordering::SeqCst);
+            if CTRLC_WATCHERS.load(atomic::Ordering::SeqCst) == 0 {
+                process::exit(130);
+            }
+        })
+        .with_context(|| "could not set the Ctrl-C handler")
+    })?;
+    CTRLC_WATCHERS.fetch_add(1, atomic::Ordering::SeqCst);
+    Ok(CtrlcGuard(()))
+}
+
+/// Whether Ctrl-C has been pressed.
+pub(crate) fn interrupted() -> bool {
+    INTERRUPTED.load(atomic::Ordering::SeqCst)
+}
+
+/// Returned by [`watch_ctrlc`].
+pub(crate) struct CtrlcGuard(());
+
+impl Drop for CtrlcGuard {
+    fn drop(&mut self) {
+        CTRLC_WATCHERS.fetch_sub(1, atomic::Ordering::SeqCst);
+    }
+}
+
+/// Child process that is killed when dropped before it exits, so that it does not outlive this
+/// process on errors or on Ctrl-C.
+struct ChildGuard {
+    child: Child,
+    _ctrlc: CtrlcGuard,
+}
+
+impl ChildGuard {
+    fn spawn(command: &mut std::process::Command) -> anyhow::Result<Self> {
+        let ctrlc = watch_ctrlc()?;
+        if interrupted() {
+            return Err(Interrupted.into());
+        }
+        Ok(Self {
+            child: command.spawn()?,
+            _ctrlc: ctrlc,
+        })
+    }
+}
+
+impl Deref for ChildGuard {
+    type Target = Child;
+
+    fn deref(&self) -> &Child {
+        &self.child
+    }
+}
+
+impl DerefMut for ChildGuard {
+    fn deref_mut(&mut self) -> &mut Child {
+        &mut self.child
+    }
+}
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        if let Ok(None) = self.child.try_wait() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
     }
 }
 
@@ -27,7 +147,9 @@ pub(crate) struct ProcessBuilder<C: Presence<PathBuf>> {
     program: OsString,
     args: Vec<OsString>,
     env: BTreeMap<String, OsString>,
+    env_remove: BTreeSet<String>,
     cwd: C::Value,
+    timeout: Option<Duration>,
 }
 
 impl<C: Presence<PathBuf>> ProcessBuilder<C> {
@@ -42,20 +164,27 @@ impl<C: Presence<PathBuf>> ProcessBuilder<C> {
     }
 
     pub(crate) fn env(mut self, key: &str, val: impl AsRef<OsStr>) -> Self {
+        self.env_remove.remove(key);
         self.env.insert(key.to_owned(), val.as_ref().to_owned());
         self
     }
 
+    /// Removes the variable `key` from the environment inherited from this process.
+    pub(crate) fn env_remove(mut self, key: &str) -> Self {
+        self.env.remove(key);
+        self.env_remove.insert(key.to_owned());
+        self
+    }
+
     pub(crate) fn envs<I, K, V>(mut self, vars: I) -> Self
     where
         I: IntoIterator<Item = (K, V)>,
         K: AsRef<str>,
         V: AsRef<OsStr>,
     {
-        self.env.extend(
-            vars.into_iter()
-                .map(|(k, v)| (k.as_ref().to_owned(), v.as_ref().to_owned())),
-        );
+        for (key, val) in vars {
+            self = self.env(key.as_ref(), val);
+        }
         self
     }
 
@@ -65,57 +194,376 @@ impl<C: Presence<PathBuf>> ProcessBuilder<C> {
             args: self.args,
             cwd: cwd.as_ref().to_owned(),
             env: self.env,
+            env_remove: self.env_remove,
+            timeout: self.timeout,
         }
     }
 }
 
 impl ProcessBuilder<Present> {
+    /// Kills the process if it does not finish in `timeout`, failing with [`TimedOut`].
+    pub(crate) fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    fn command(&self) -> std::process::Command {
+        let mut command = std::process::Command::new(&self.program);
+        command.args(&self.args);
+        for key in &self.env_remove {
+            command.env_remove(key);
+        }
+        command.envs(&self.env).current_dir(&self.cwd);
+        command
+    }
+
     fn output(&self, check: bool, stdout: Stdio, stderr: Stdio) -> anyhow::Result<Output> {
-        let output = std::process::Command::new(&self.program)
-            .args(&self.args)
-            .envs(&self.env)
-            .current_dir(&self.cwd)
-            .stdout(stdout)
-            .stderr(stderr)
-            .output()?;
+        let child = ChildGuard::spawn(self.command().stdout(stdout).stderr(stderr))?;
+        let output = self.wait_with_output(child)?;
+
         if check && !output.status.success() {
             bail!("{} didn't exit successfully: {}", self, output.status);
         }
         Ok(output)
     }
 
+    /// Waits for `child` collecting the piped stdout and stderr, killing it on the timeout.
+    fn wait_with_output(&self, mut child: ChildGuard) -> anyhow::Result<Output> {
+        // Read the pipes in other threads so that the child does not block on a full pipe.
+        let stdout = child.stdout.take().map(read_to_end);
+        let stderr = child.stderr.take().map(read_to_end);
+
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let status = self.wait(&mut child, deadline)?;
+
+        let join = |handle: Option<thread::JoinHandle<_>>| -> io::Result<_> {
+            Ok(match handle {
+                Some(handle) => handle.join().unwrap()?,
+                None => vec![],
+            })
+        };
+        Ok(Output {
+            status,
+            stdout: join(stdout)?,
+            stderr: join(stderr)?,
+        })
+    }
+
+    /// Waits for `child`, killing it at `deadline` or on Ctrl-C.
+    fn wait(&self, child: &mut Child, deadline: Option<Instant>) -> anyhow::Result<ExitStatus> {
+        loop {
+            if let Some(status) = child.try_wait()? {
+                break Ok(status);
+            }
+            self.kill_if_interrupted_or_timed_out(child, deadline)?;
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Kills `child` and fails with [`Interrupted`] or [`TimedOut`] if Ctrl-C has been pressed or
+    /// `deadline` has passed.
+    fn kill_if_interrupted_or_timed_out(
+        &self,
+        child: &mut Child,
+        deadline: Option<Instant>,
+    ) -> anyhow::Result<()> {
+        let err = if interrupted() {
+            Interrupted.into()
+        } else if matches!(deadline, Some(deadline) if Instant::now() >= deadline) {
+            self.timed_out()
+        } else {
+            return Ok(());
+        };
+        child.kill()?;
+        child.wait()?;
+        Err(err)
+    }
+
+    fn timed_out(&self) -> anyhow::Error {
+        TimedOut {
+            process: self.to_string(),
+            timeout: self.timeout.unwrap_or_default(),
+        }
+        .into()
+    }
+
+    /// Runs the process, calling `on_line` with each line of stdout (`true`) and stderr (`false`)
+    /// as soon as it is printed. Returns the exit status and the whole stdout.
+    fn output_lines(
+        &self,
+        mut on_line: impl FnMut(bool, &[u8]) -> io::Result<()>,
+    ) -> anyhow::Result<(ExitStatus, Vec<u8>)> {
+        let mut child =
+            ChildGuard::spawn(self.command().stdout(Stdio::piped()).stderr(Stdio::piped()))?;
+
+        let (tx, rx) = mpsc::channel();
+        if let Some(stdout) = child.stdout.take() {
+            send_lines(stdout, true, tx.clone());
+        }
+        if let Some(stderr) = child.stderr.take() {
+            send_lines(stderr, false, tx);
+        }
+
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let mut stdout = vec![];
+        loop {
+            // Checked on every line too, since a process printing continuously would otherwise
+            // never be killed.
+            self.kill_if_interrupted_or_timed_out(&mut child, deadline)?;
+            // Wake up periodically to check for Ctrl-C.
+            let (is_stdout, line) = match rx.recv_timeout(Duration::from_millis(100)) {
+                Ok(msg) => msg,
+                Err(RecvTimeoutError::Disconnected) => break,
+                Err(RecvTimeoutError::Timeout) => continue,
+            };
+            let line = line?;
+            on_line(is_stdout, &line)?;
+            if is_stdout {
+                stdout.extend(line);
+            }
+        }
+
+        let status = self.wait(&mut child, deadline)?;
+        Ok((status, stdout))
+    }
+
     pub(crate) fn exec(&self) -> anyhow::Result<()> {
         self.output(true, Stdio::inherit(), Stdio::inherit())?;
         Ok(())
     }
 
+    /// Executes the process. In quiet mode, its stdout is redirected to stderr.
     pub(crate) fn exec_with_status(&self, shell: &mut Shell) -> anyhow::Result<()> {
         shell.status("Running", self)?;
-        self.exec()
+        let started = Instant::now();
+        if shell.is_quiet() {
+            // `Stdio: From<io::Stderr>` requires Rust 1.74, so the lines are forwarded instead.
+            let (status, _) = self.output_lines(|_, line| {
+                shell.err().write_all(line)?;
+                shell.err().flush()
+            })?;
+            if !status.success() {
+                bail!("{} didn't exit successfully: {}", self, status);
+            }
+        } else {
+            self.exec()?;
+        }
+        self.print_elapsed(started, Verbosity::Verbose, shell)?;
+        Ok(())
     }
 
-    pub(crate) fn status_silent(&self) -> anyhow::Result<ExitStatus> {
+    pub(crate) fn status_silent(&self, shell: &mut Shell) -> anyhow::Result<ExitStatus> {
+        shell.status_with_verbosity(Verbosity::VeryVerbose, "Running", self)?;
+        let started = Instant::now();
         let Output { status, .. } = self.output(false, Stdio::null(), Stdio::null())?;
+        self.print_elapsed(started, Verbosity::VeryVerbose, shell)?;
         Ok(status)
     }
 
+    /// Prints how long the process took since `started`, if the verbosity is `verbosity` or
+    /// higher.
+    fn print_elapsed(
+        &self,
+        started: Instant,
+        verbosity: Verbosity,
+        shell: &mut Shell,
+    ) -> io::Result<()> {
+        shell.status_with_verbosity(
+            verbosity,
+            "Finished",
+            format!("{} in {:.1}s", self, started.elapsed().as_secs_f64()),
+        )
+    }
+
+    /// Runs the process capturing its stdout, and returns it. The stderr is inherited.
     pub(crate) fn read(&self, check: bool) -> anyhow::Result<String> {
-        let Output { stdout, .. } = self.output(check, Stdio::piped(), Stdio::inherit())?;
-        let stdout =
-            str::from_utf8(&stdout).map_err(|_| anyhow!("stream did not contain valid UTF-8"))?;
-        Ok(stdout.trim_end().to_owned())
+        decode_stdout(&self.read_bytes(check)?)
     }
 
-    pub(crate) fn read_with_status(
-        &self,
-        check: bool,
-        shell: &mut Shell,
-    ) -> anyhow::Result<String> {
+    /// Runs the process capturing its stdout, and returns it as it is, without requiring it to be
+    /// UTF-8. The stderr is inherited.
+    pub(crate) fn read_bytes(&self, check: bool) -> anyhow::Result<Vec<u8>> {
+        self.read_stdout(check, false)
+    }
+
+    /// Runs the process capturing both stdout and stderr, and returns the stdout. If `check` is
+    /// `true` and the process fails, the stderr is included in the error.
+    pub(crate) fn read_captured(&self, check: bool) -> anyhow::Result<String> {
+        decode_stdout(&self.read_stdout(check, true)?)
+    }
+
+    /// Runs the process capturing its stdout, and returns it without trimming it. The stderr is
+    /// captured and included in the error if `capture_stderr` is `true`, and inherited otherwise.
+    fn read_stdout(&self, check: bool, capture_stderr: bool) -> anyhow::Result<Vec<u8>> {
+        let stderr = if capture_stderr {
+            Stdio::piped()
+        } else {
+            Stdio::inherit()
+        };
+        let Output {
+            status,
+            stdout,
+            stderr,
+        } = self.output(false, Stdio::piped(), stderr)?;
+        if check && !status.success() {
+            let stderr = String::from_utf8_lossy(&stderr);
+            let err = anyhow!("{} didn't exit successfully: {}", self, status);
+            return Err(match stderr.trim() {
+                "" => err,
+                stderr => anyhow!("{}", stderr).context(err),
+            });
+        }
+        Ok(stdout)
+    }
+
+    /// Runs the process with `input` written to the stdin, and returns the stdout without
+    /// trimming it. If the process fails, the stderr is included in the error.
+    pub(crate) fn read_with_stdin(&self, input: &str) -> anyhow::Result<String> {
+        let mut child = ChildGuard::spawn(
+            self.command()
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped()),
+        )?;
+
+        // Write in another thread so that the child does not block on a full stdout pipe.
+        let mut stdin = child.stdin.take().expect("should be piped");
+        let input = input.to_owned();
+        let writer = thread::spawn(move || stdin.write_all(input.as_ref()));
+
+        let Output {
+            status,
+            stdout,
+            stderr,
+        } = self.wait_with_output(child)?;
+        writer.join().unwrap()?;
+
+        if !status.success() {
+            let stderr = String::from_utf8_lossy(&stderr);
+            let err = anyhow!("{} didn't exit successfully: {}", self, status);
+            return Err(match stderr.trim() {
+                "" => err,
+                stderr => anyhow!("{}", stderr).context(err),
+            });
+        }
+        String::from_utf8(stdout).map_err(|_| anyhow!("stream did not contain valid UTF-8"))
+    }
+
+    /// Executes the process, printing its stdout and stderr to the stderr of `shell` line by line,
+    /// and returns the stdout.
+    pub(crate) fn tee_with_status(&self, check: bool, shell: &mut Shell) -> anyhow::Result<String> {
         shell.status("Running", self)?;
-        self.read(check)
+        let started = Instant::now();
+        let (status, stdout) = self.output_lines(|_, line| {
+            shell.err().write_all(line)?;
+            shell.err().flush()
+        })?;
+        self.print_elapsed(started, Verbosity::Verbose, shell)?;
+        if check && !status.success() {
+            bail!("{} didn't exit successfully: {}", self, status);
+        }
+        decode_stdout(&stdout)
+    }
+
+    /// Runs the process, printing each line of its stdout and stderr with `prefix`. In quiet mode,
+    /// its stdout is redirected to stderr.
+    pub(crate) fn stream_with_prefix(
+        &self,
+        prefix: &str,
+        shell: &Mutex<&mut Shell>,
+    ) -> anyhow::Result<ExitStatus> {
+        let started = Instant::now();
+        let (status, _) = self.output_lines(|is_stdout, line| {
+            let mut shell = shell.lock().unwrap();
+            let wtr: &mut dyn Write = if is_stdout && !shell.is_quiet() {
+                shell.out()
+            } else {
+                shell.err()
+            };
+            write!(wtr, "{} ", prefix)?;
+            wtr.write_all(line)?;
+            if !line.ends_with(b"\n") {
+                writeln!(wtr)?;
+            }
+            wtr.flush()
+        })?;
+        self.print_elapsed(started, Verbosity::Verbose, &mut shell.lock().unwrap())?;
+        Ok(status)
+    }
+}
+
+/// Sends each line in `rdr` to `tx` in another thread, along with `is_stdout`.
+fn send_lines(
+    rdr: impl Read + Send + 'static,
+    is_stdout: bool,
+    tx: mpsc::Sender<(bool, io::Result<Vec<u8>>)>,
+) {
+    thread::spawn(move || {
+        let mut rdr = BufReader::new(rdr);
+        loop {
+            let mut line = vec![];
+            match rdr.read_until(b'\n', &mut line) {
+                Ok(0) => break,
+                Ok(_) => {
+                    if tx.send((is_stdout, Ok(line))).is_err() {
+                        break;
+                    }
+                }
+                Err(err) => {
+                    let _ = tx.send((is_stdout, Err(err)));
+                    break;
+                }
+            }
+        }
+    });
+}
+
+fn decode_stdout(stdout: &[u8]) -> anyhow::Result<String> {
+    let stdout =
+        str::from_utf8(stdout).map_err(|_| anyhow!("stream did not contain valid UTF-8"))?;
+    Ok(stdout.trim_end().to_owned())
+}
+
+fn read_to_end(mut rdr: impl Read + Send + 'static) -> thread::JoinHandle<io::Result<Vec<u8>>> {
+    thread::spawn(move || {
+        let mut buf = vec![];
+        rdr.read_to_end(&mut buf)?;
+        Ok(buf)
+    })
+}
+
+/// Error for a process killed by [`ProcessBuilder::timeout`].
+#[derive(Debug)]
+pub(crate) struct TimedOut {
+    process: String,
+    timeout: Duration,
+}
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            fmt,
+            "{} timed out after {}s",
+            self.process,
+            self.timeout.as_secs_f64(),
+        )
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// Error for a process killed on Ctrl-C.
+#[derive(Debug)]
+pub(crate) struct Interrupted;
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "interrupted")
     }
 }
 
+impl std::error::Error for Interrupted {}
+
 impl fmt::Display for ProcessBuilder<Present> {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
@@ -147,3 +595,73 @@ pub(crate) enum Present {}
 impl<T> Presence<T> for Present {
     type Value = T;
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::{process, TimedOut};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn output_lines_times_out_on_continuous_output() {
+        let started = Instant::now();
+        let result = process("sh")
+            .args(&["-c", "while :; do echo .; sleep 0.01; done"])
+            .cwd(".")
+            .timeout(Duration::from_millis(500))
+            .output_lines(|_, _| Ok(()));
+        assert!(result.unwrap_err().is::<TimedOut>());
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn output_times_out() {
+        let result = process("sleep")
+            .arg("10")
+            .cwd(".")
+            .timeout(Duration::from_millis(100))
+            .read(false);
+        assert!(result.unwrap_err().is::<TimedOut>());
+    }
+
+    #[test]
+    fn env_remove() {
+        let echo = |key: &str| {
+            process("sh")
+                .args(&["-c", &format!("echo ${{{}-unset}}", key)])
+                .env("CARGO_CPL_TEST", "1")
+        };
+        assert_eq!("1", echo("CARGO_CPL_TEST").cwd(".").read(true).unwrap());
+        assert_eq!(
+            "unset",
+            echo("CARGO_CPL_TEST")
+                .env_remove("CARGO_CPL_TEST")
+                .cwd(".")
+                .read(true)
+                .unwrap(),
+        );
+        // Set by `cargo test` and inherited from this process.
+        let key = "CARGO_MANIFEST_DIR";
+        assert_ne!("unset", echo(key).cwd(".").read(true).unwrap());
+        assert_eq!(
+            "unset",
+            echo(key).env_remove(key).cwd(".").read(true).unwrap(),
+        );
+    }
+
+    #[test]
+    fn read_non_utf8() {
+        let printf = process("printf").arg("\\377\\n").cwd(".");
+        assert_eq!(b"\xff\n", &*printf.read_bytes(true).unwrap());
+        assert!(printf.read(true).is_err());
+    }
+
+    #[test]
+    fn read_captured_includes_stderr_in_error() {
+        let err = process("sh")
+            .args(&["-c", "echo oops >&2; exit 1"])
+            .cwd(".")
+            .read_captured(true)
+            .unwrap_err();
+        assert_eq!("oops", err.root_cause().to_string());
+    }
+}
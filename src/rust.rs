@@ -1,156 +1,723 @@
 use camino::Utf8Path;
 use if_chain::if_chain;
 use itertools::Itertools as _;
-use proc_macro2::{LineColumn, TokenStream, TokenTree};
+use proc_macro2::{Delimiter, LineColumn, Spacing, TokenStream, TokenTree};
+use quote::ToTokens;
 use std::collections::BTreeMap;
-use syn::{spanned::Spanned as _, Attribute, File, Item, ItemMod, Lit, Meta, MetaNameValue};
+use syn::{
+    spanned::Spanned as _, Attribute, File, ImplItem, ImplItemConst, ImplItemMacro, ImplItemMethod,
+    ImplItemType, Item, ItemConst, ItemEnum, ItemExternCrate, ItemFn, ItemForeignMod, ItemImpl,
+    ItemMacro, ItemMacro2, ItemMod, ItemStatic, ItemStruct, ItemTrait, ItemTraitAlias, ItemType,
+    ItemUnion, ItemUse, Lit, Meta, MetaList, MetaNameValue, NestedMeta,
+};
 
-pub(crate) fn expand_mods(src_path: &Utf8Path) -> Result<String, String> {
-    return expand_mods(src_path, 0);
+/// Removes the comments including the doc comments, leaving the string literals untouched.
+pub(crate) fn strip_comments(code: &str) -> String {
+    let mut ret = "".to_owned();
+    let mut chars = code.chars().peekable();
+    // Length of the identifier-like run before `c`, to tell `r"`/`br"` from `foo"`.
+    let mut ident_run = 0;
+    let mut prev = '\0';
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.peek() == Some(&'/') => {
+                while !matches!(chars.peek(), None | Some('\n')) {
+                    chars.next();
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut depth = 1;
+                while depth > 0 {
+                    match (chars.next(), chars.peek()) {
+                        (Some('/'), Some('*')) => {
+                            chars.next();
+                            depth += 1;
+                        }
+                        (Some('*'), Some('/')) => {
+                            chars.next();
+                            depth -= 1;
+                        }
+                        (Some(_), _) => {}
+                        (None, _) => break,
+                    }
+                }
+                ret.push(' ');
+            }
+            '"' => {
+                ret.push(c);
+                while let Some(c) = chars.next() {
+                    ret.push(c);
+                    match c {
+                        '\\' => ret.extend(chars.next()),
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            'r' if (ident_run == 0 || ident_run == 1 && prev == 'b')
+                && matches!(chars.peek(), Some('"') | Some('#')) =>
+            {
+                ret.push(c);
+                let mut num_hashes = 0;
+                while chars.peek() == Some(&'#') {
+                    ret.push(chars.next().unwrap());
+                    num_hashes += 1;
+                }
+                if chars.peek() != Some(&'"') {
+                    // e.g. `r#ident`
+                    ident_run += 1;
+                    prev = c;
+                    continue;
+                }
+                ret.push(chars.next().unwrap());
+                // Compared only with the content, so that the opening `"` is not taken for the
+                // closing one in `r#"#..."#`.
+                let terminator = format!("\"{}", "#".repeat(num_hashes));
+                let mut content = "".to_owned();
+                for c in chars.by_ref() {
+                    content.push(c);
+                    if content.ends_with(&terminator) {
+                        break;
+                    }
+                }
+                ret += &content;
+                ident_run = 0;
+                prev = '"';
+                continue;
+            }
+            '\'' => {
+                ret.push(c);
+                let mut lookahead = chars.clone();
+                let is_char_lit = matches!(
+                    (lookahead.next(), lookahead.next()),
+                    (Some('\\'), _) | (Some(_), Some('\''))
+                );
+                if is_char_lit {
+                    while let Some(c) = chars.next() {
+                        ret.push(c);
+                        match c {
+                            '\\' => ret.extend(chars.next()),
+                            '\'' => break,
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            c => ret.push(c),
+        }
+        ident_run = if c == '_' || c.is_alphanumeric() {
+            ident_run + 1
+        } else {
+            0
+        };
+        prev = c;
+    }
+    ret
+}
+
+/// Strips the comments with [`strip_comments`] and removes the whitespace outside of the literals
+/// except where it separates tokens.
+pub(crate) fn minify(code: &str) -> Result<String, String> {
+    let token_stream = strip_comments(code)
+        .parse::<TokenStream>()
+        .map_err(|e| format!("could not parse the code: {}", e))?;
+    let mut ret = "".to_owned();
+    write(token_stream, &mut ret);
+    return Ok(ret);
+
+    fn write(token_stream: TokenStream, ret: &mut String) {
+        let mut prev = None;
+        for tt in token_stream {
+            if needs_space(prev.as_ref(), &tt) {
+                ret.push(' ');
+            }
+            match &tt {
+                TokenTree::Group(group) => {
+                    let (open, close) = match group.delimiter() {
+                        Delimiter::Parenthesis => ("(", ")"),
+                        Delimiter::Brace => ("{", "}"),
+                        Delimiter::Bracket => ("[", "]"),
+                        Delimiter::None => ("", ""),
+                    };
+                    *ret += open;
+                    write(group.stream(), ret);
+                    *ret += close;
+                }
+                TokenTree::Punct(punct) => ret.push(punct.as_char()),
+                tt => *ret += &tt.to_string(),
+            }
+            prev = Some(tt);
+        }
+    }
+
+    /// Whether `prev` and `next` are both identifiers or literals, or `prev` is a punctuation that
+    /// may be joined with `next` (e.g. `< -` and `<-`).
+    fn needs_space(prev: Option<&TokenTree>, next: &TokenTree) -> bool {
+        let is_word = |tt: &TokenTree| matches!(tt, TokenTree::Ident(_) | TokenTree::Literal(_));
+        match (prev, next) {
+            (Some(TokenTree::Punct(prev)), TokenTree::Punct(_)) => prev.spacing() == Spacing::Alone,
+            (Some(prev), next) => is_word(prev) && is_word(next),
+            (None, _) => false,
+        }
+    }
+}
 
-    fn expand_mods(src_path: &Utf8Path, depth: usize) -> Result<String, String> {
+/// Replaces `crate::` and `$crate::` with `crate::{name}::`, for putting the code in a module.
+pub(crate) fn prefix_crate_paths(code: &str, name: &str) -> Result<String, String> {
+    let token_stream = code
+        .parse::<TokenStream>()
+        .map_err(|e| format!("could not parse the code: {}", e))?;
+    let mut replacements = BTreeMap::new();
+    collect(token_stream, name, &mut replacements);
+    return Ok(replace_ranges(code, replacements));
+
+    fn collect(
+        token_stream: TokenStream,
+        name: &str,
+        replacements: &mut BTreeMap<(LineColumn, LineColumn), String>,
+    ) {
+        let mut token_trees = token_stream.into_iter().peekable();
+        while let Some(tt) = token_trees.next() {
+            match tt {
+                TokenTree::Ident(ident) if ident == "crate" => {
+                    if matches!(token_trees.peek(), Some(TokenTree::Punct(p)) if p.as_char() == ':')
+                    {
+                        let pos = ident.span().end();
+                        replacements.insert((pos, pos), format!("::{}", name));
+                    }
+                }
+                TokenTree::Group(group) => collect(group.stream(), name, replacements),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Inlines the file modules.
+///
+/// Modules with `#[cfg(test)]` are removed. If `features` is `Some`, modules disabled with
+/// `#[cfg(feature = "...")]` are removed as well.
+///
+/// The inlined modules are indented with `indent_width` spaces per level. `0` keeps the original
+/// formatting.
+pub(crate) fn expand_mods(
+    src_path: &Utf8Path,
+    features: Option<&[String]>,
+    indent_width: usize,
+) -> Result<String, String> {
+    return expand_mods(
+        src_path,
+        Cx {
+            features,
+            indent_width,
+            file: src_path,
+        },
+        0,
+    );
+
+    #[derive(Clone, Copy)]
+    struct Cx<'a> {
+        features: Option<&'a [String]>,
+        indent_width: usize,
+        /// File being expanded, for the error messages.
+        file: &'a Utf8Path,
+    }
+
+    fn expand_mods(src_path: &Utf8Path, cx: Cx<'_>, depth: usize) -> Result<String, String> {
+        let cx = Cx {
+            file: src_path,
+            ..cx
+        };
         let code = &read_file(src_path)?;
         let File { items, .. } =
             syn::parse_file(code).map_err(|e| format!("could not parse `{}`: {}", src_path, e))?;
 
-        let replacements = items
-            .into_iter()
-            .flat_map(|item| match item {
+        let file_dir = src_path.with_file_name("");
+        let mod_dir = if depth == 0 || src_path.file_name() == Some("mod.rs") {
+            file_dir.clone()
+        } else {
+            src_path.with_extension("")
+        };
+
+        let mut replacements = BTreeMap::new();
+        collect_replacements(items, &file_dir, &mod_dir, cx, depth, 0, &mut replacements)?;
+        Ok(replace_ranges(code, replacements))
+    }
+
+    /// Expands the file modules in `items`, including ones in inline modules.
+    ///
+    /// `path_dir` is the base directory for `#[path = "..."]`, and `mod_dir` is the directory of
+    /// the submodule files. `level` is the number of the enclosing inline modules.
+    fn collect_replacements(
+        items: Vec<Item>,
+        path_dir: &Utf8Path,
+        mod_dir: &Utf8Path,
+        cx: Cx<'_>,
+        depth: usize,
+        level: usize,
+        replacements: &mut BTreeMap<(LineColumn, LineColumn), String>,
+    ) -> Result<(), String> {
+        for item in items {
+            let (attrs, ident, content, semi) = match item {
                 Item::Mod(ItemMod {
                     attrs,
                     ident,
-                    content: None,
+                    content,
                     semi,
                     ..
-                }) => Some((attrs, ident, semi)),
-                _ => None,
-            })
-            .map(|(attrs, ident, semi)| {
-                let paths = if let Some(path) = attrs
-                    .iter()
-                    .flat_map(Attribute::parse_meta)
-                    .flat_map(|meta| match meta {
-                        Meta::NameValue(name_value) => Some(name_value),
-                        _ => None,
-                    })
-                    .filter(|MetaNameValue { path, .. }| {
-                        matches!(path.get_ident(), Some(i) if i == "path")
-                    })
-                    .find_map(|MetaNameValue { lit, .. }| match lit {
-                        Lit::Str(s) => Some(s.value()),
-                        _ => None,
-                    }) {
-                        vec![src_path.with_file_name("").join(path)]
-                    } else if depth == 0 || src_path.file_name() == Some("mod.rs") {
-                        vec![
-                            src_path
-                                .with_file_name(&ident.to_string())
-                                .with_extension("rs"),
-                            src_path.with_file_name(&ident.to_string()).join("mod.rs"),
-                        ]
-                    } else {
-                        vec![
-                            src_path
-                                .with_extension("")
-                                .with_file_name(&ident.to_string())
-                                .with_extension("rs"),
-                            src_path
-                                .with_extension("")
-                                .with_file_name(&ident.to_string())
-                                .join("mod.rs"),
-                        ]
-                    };
+                }) => (attrs, ident, content, semi),
+                _ => continue,
+            };
 
-                if let Some(path) = paths.iter().find(|p| p.exists()) {
-                    let start = semi.span().start();
-                    let end = semi.span().end();
-                    let content = expand_mods(&path, depth + 1)?;
-                    let content = indent_code(&content, depth + 1);
-                    let content = format!(" {{\n{}{}}}", content, "    ".repeat(depth + 1));
-                    Ok(((start, end), content))
-                } else {
-                    Err(format!("one of {:?} does not exist", paths))
-                }
-            })
-            .collect::<Result<BTreeMap<_, _>, _>>()?;
+            if is_disabled(&attrs, cx.features) {
+                let start = attrs[0].pound_token.span.start();
+                let end = match (&content, &semi) {
+                    (Some((brace, _)), _) => brace.span.end(),
+                    (None, Some(semi)) => semi.span().end(),
+                    (None, None) => unreachable!(),
+                };
+                replacements.insert((start, end), "".to_owned());
+                continue;
+            }
 
-        Ok(replace_ranges(code, replacements))
+            if let Some((_, items)) = content {
+                let mod_dir = mod_dir.join(ident.to_string());
+                collect_replacements(
+                    items,
+                    &mod_dir,
+                    &mod_dir,
+                    cx,
+                    depth,
+                    level + 1,
+                    replacements,
+                )?;
+                continue;
+            }
+
+            let paths = if let Some(path) = attrs
+                .iter()
+                .flat_map(Attribute::parse_meta)
+                .flat_map(|meta| match meta {
+                    Meta::NameValue(name_value) => Some(name_value),
+                    _ => None,
+                })
+                .filter(
+                    |MetaNameValue { path, .. }| matches!(path.get_ident(), Some(i) if i == "path"),
+                )
+                .find_map(|MetaNameValue { lit, .. }| match lit {
+                    Lit::Str(s) => Some(s.value()),
+                    _ => None,
+                }) {
+                // Also accepts a directory containing `mod.rs`.
+                let path = path_dir.join(path);
+                vec![path.clone(), path.join("mod.rs")]
+            } else {
+                vec![
+                    mod_dir.join(ident.to_string()).with_extension("rs"),
+                    mod_dir.join(ident.to_string()).join("mod.rs"),
+                ]
+            };
+
+            if let Some(path) = paths.iter().find(|p| p.is_file()) {
+                let start = semi.span().start();
+                let end = semi.span().end();
+                let content = expand_mods(path, cx, depth + 1)?;
+                let content = indent_code(&content, level + 1, cx.indent_width);
+                let content = format!(" {{\n{}{}}}", content, " ".repeat(cx.indent_width * level));
+                replacements.insert((start, end), content);
+            } else {
+                let LineColumn { line, column } = ident.span().start();
+                return Err(format!(
+                    "{}:{}:{}: could not find the file for `mod {}`: one of {:?} does not exist",
+                    cx.file,
+                    line,
+                    column + 1,
+                    ident,
+                    paths,
+                ));
+            }
+        }
+        Ok(())
     }
+}
+
+/// Reads the file, removing a BOM and normalizing CRLF to LF so that the columns of the spans
+/// match `replace_ranges`.
+fn read_file(path: &Utf8Path) -> Result<String, String> {
+    let code = xshell::read_file(path).map_err(|e| e.to_string())?;
+    Ok(code.trim_start_matches('\u{feff}').replace("\r\n", "\n"))
+}
+
+/// Removes the items disabled with `#[cfg(test)]` and the `#[test]` functions, including ones in
+/// inline modules and `impl` blocks.
+pub(crate) fn remove_test_items(code: &str) -> Result<String, String> {
+    let File { items, .. } =
+        syn::parse_file(code).map_err(|e| format!("could not parse the code: {}", e))?;
+    let mut replacements = BTreeMap::new();
+    collect(&items, &mut replacements);
+    return Ok(replace_ranges(code, replacements));
 
-    fn read_file(path: &Utf8Path) -> Result<String, String> {
-        xshell::read_file(path).map_err(|e| e.to_string())
+    fn collect(items: &[Item], replacements: &mut BTreeMap<(LineColumn, LineColumn), String>) {
+        for item in items {
+            let attrs = match item {
+                Item::Const(ItemConst { attrs, .. })
+                | Item::Enum(ItemEnum { attrs, .. })
+                | Item::ExternCrate(ItemExternCrate { attrs, .. })
+                | Item::Fn(ItemFn { attrs, .. })
+                | Item::ForeignMod(ItemForeignMod { attrs, .. })
+                | Item::Impl(ItemImpl { attrs, .. })
+                | Item::Macro(ItemMacro { attrs, .. })
+                | Item::Macro2(ItemMacro2 { attrs, .. })
+                | Item::Mod(ItemMod { attrs, .. })
+                | Item::Static(ItemStatic { attrs, .. })
+                | Item::Struct(ItemStruct { attrs, .. })
+                | Item::Trait(ItemTrait { attrs, .. })
+                | Item::TraitAlias(ItemTraitAlias { attrs, .. })
+                | Item::Type(ItemType { attrs, .. })
+                | Item::Union(ItemUnion { attrs, .. })
+                | Item::Use(ItemUse { attrs, .. }) => attrs,
+                _ => continue,
+            };
+            if is_test(attrs) {
+                remove(attrs, item, replacements);
+            } else if let Item::Mod(ItemMod {
+                content: Some((_, items)),
+                ..
+            }) = item
+            {
+                collect(items, replacements);
+            } else if let Item::Impl(ItemImpl { items, .. }) = item {
+                for item in items {
+                    let attrs = match item {
+                        ImplItem::Const(ImplItemConst { attrs, .. })
+                        | ImplItem::Method(ImplItemMethod { attrs, .. })
+                        | ImplItem::Type(ImplItemType { attrs, .. })
+                        | ImplItem::Macro(ImplItemMacro { attrs, .. }) => attrs,
+                        _ => continue,
+                    };
+                    if is_test(attrs) {
+                        remove(attrs, item, replacements);
+                    }
+                }
+            }
+        }
     }
 
-    fn indent_code(code: &str, n: usize) -> String {
-        let is_safe_to_indent = code.parse::<TokenStream>().map_or(false, |token_stream| {
-            !token_stream.into_iter().any(|tt| {
-                matches!(
-                    tt, TokenTree::Literal(lit)
-                    if lit.span().start().line != lit.span().end().line
-                )
-            })
-        });
+    fn is_test(attrs: &[Attribute]) -> bool {
+        is_disabled(attrs, None) || attrs.iter().any(|a| a.path.is_ident("test"))
+    }
 
-        if is_safe_to_indent {
-            code.lines()
-                .map(|line| match line {
-                    "" => "\n".to_owned(),
-                    line => format!("{}{}\n", "    ".repeat(n), line),
-                })
-                .join("")
-        } else {
-            code.to_owned()
+    fn remove(
+        attrs: &[Attribute],
+        item: &impl ToTokens,
+        replacements: &mut BTreeMap<(LineColumn, LineColumn), String>,
+    ) {
+        // The last token is `;` or a group, whose span covers the whole group.
+        if let Some(last) = item.to_token_stream().into_iter().last() {
+            let start = attrs[0].pound_token.span.start();
+            replacements.insert((start, last.span().end()), "".to_owned());
         }
     }
+}
 
-    fn replace_ranges(
-        code: &str,
-        replacements: BTreeMap<(LineColumn, LineColumn), String>,
-    ) -> String {
-        let replacements = replacements.into_iter().collect::<Vec<_>>();
-        let mut replacements = &*replacements;
-        let mut skip_until = None;
-        let mut ret = "".to_owned();
-        let mut lines = code.trim_end().split('\n').enumerate().peekable();
-        while let Some((i, s)) = lines.next() {
-            for (j, c) in s.chars().enumerate() {
-                if_chain! {
-                    if let Some(((start, end), replacement)) = replacements.get(0);
-                    if (i, j) == (start.line - 1, start.column);
-                    then {
-                        ret += replacement;
-                        if start == end {
-                            ret.push(c);
-                        } else {
-                            skip_until = Some(*end);
-                        }
-                        replacements = &replacements[1..];
-                    } else {
-                        if !matches!(skip_until, Some(LineColumn { line, column }) if (i, j) < (line - 1, column)) {
-                            ret.push(c);
-                            skip_until = None;
-                        }
-                    }
+/// Whether `#[cfg(..)]` in `attrs` is evaluated to `false`. `feature = ".."` is evaluated only if
+/// `features` is `Some`.
+fn is_disabled(attrs: &[Attribute], features: Option<&[String]>) -> bool {
+    return attrs
+        .iter()
+        .flat_map(Attribute::parse_meta)
+        .flat_map(|meta| match meta {
+            Meta::List(MetaList { path, nested, .. }) if path.is_ident("cfg") => {
+                nested.into_iter().next()
+            }
+            _ => None,
+        })
+        .any(|pred| eval(&pred, features) == Some(false));
+
+    fn eval(pred: &NestedMeta, features: Option<&[String]>) -> Option<bool> {
+        match pred {
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("test") => Some(false),
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                path,
+                lit: Lit::Str(name),
+                ..
+            })) if path.is_ident("feature") => {
+                features.map(|features| features.contains(&name.value()))
+            }
+            NestedMeta::Meta(Meta::List(MetaList { path, nested, .. })) => {
+                let values = nested
+                    .iter()
+                    .map(|pred| eval(pred, features))
+                    .collect::<Vec<_>>();
+                let (short_circuit, otherwise) = if path.is_ident("all") {
+                    (false, true)
+                } else if path.is_ident("any") {
+                    (true, false)
+                } else if path.is_ident("not") && values.len() == 1 {
+                    return values[0].map(|v| !v);
+                } else {
+                    return None;
+                };
+                if values.contains(&Some(short_circuit)) {
+                    Some(short_circuit)
+                } else if values.iter().all(|v| *v == Some(otherwise)) {
+                    Some(otherwise)
+                } else {
+                    None
                 }
             }
-            while let Some(((start, end), replacement)) = replacements.get(0) {
-                if i == start.line - 1 {
+            _ => None,
+        }
+    }
+}
+
+/// Returns the first and the last lines of `fn main`, 1-based.
+pub(crate) fn main_fn_lines(src_path: &Utf8Path) -> Result<Option<(usize, usize)>, String> {
+    let code = &read_file(src_path)?;
+    let File { items, .. } =
+        syn::parse_file(code).map_err(|e| format!("could not parse `{}`: {}", src_path, e))?;
+    Ok(items.iter().find_map(|item| match item {
+        Item::Fn(item_fn) if item_fn.sig.ident == "main" => {
+            let span = item_fn.span();
+            Some((span.start().line, span.end().line))
+        }
+        _ => None,
+    }))
+}
+
+/// Indents `code` by `n` levels of `width` spaces, unless it contains multi-line literals.
+///
+/// `width == 0` leaves `code` as it is.
+pub(crate) fn indent_code(code: &str, n: usize, width: usize) -> String {
+    if width == 0 {
+        return code.to_owned();
+    }
+
+    let is_safe_to_indent = match code.parse::<TokenStream>() {
+        Ok(token_stream) => !token_stream.into_iter().any(|tt| {
+            matches!(
+                tt, TokenTree::Literal(lit)
+                if lit.span().start().line != lit.span().end().line
+            )
+        }),
+        Err(_) => false,
+    };
+
+    if is_safe_to_indent {
+        code.lines()
+            .map(|line| match line {
+                "" => "\n".to_owned(),
+                line => format!("{}{}\n", " ".repeat(n * width), line),
+            })
+            .join("")
+    } else {
+        code.to_owned()
+    }
+}
+
+fn replace_ranges(code: &str, replacements: BTreeMap<(LineColumn, LineColumn), String>) -> String {
+    let replacements = replacements.into_iter().collect::<Vec<_>>();
+    let mut replacements = &*replacements;
+    let mut skip_until = None;
+    let mut ret = "".to_owned();
+    let mut lines = code.trim_end().split('\n').enumerate().peekable();
+    while let Some((i, s)) = lines.next() {
+        for (j, c) in s.chars().enumerate() {
+            if_chain! {
+                if let Some(((start, end), replacement)) = replacements.first();
+                if (i, j) == (start.line - 1, start.column);
+                then {
                     ret += replacement;
-                    if start < end {
+                    if start == end {
+                        ret.push(c);
+                    } else {
                         skip_until = Some(*end);
                     }
                     replacements = &replacements[1..];
                 } else {
-                    break;
+                    if !matches!(skip_until, Some(LineColumn { line, column }) if (i, j) < (line - 1, column)) {
+                        ret.push(c);
+                        skip_until = None;
+                    }
                 }
             }
-            if lines.peek().is_some() || code.ends_with('\n') {
-                ret += "\n";
+        }
+        while let Some(((start, end), replacement)) = replacements.first() {
+            if i == start.line - 1 {
+                ret += replacement;
+                if start < end {
+                    skip_until = Some(*end);
+                }
+                replacements = &replacements[1..];
+            } else {
+                break;
             }
         }
+        if lines.peek().is_some() || code.ends_with('\n') {
+            ret += "\n";
+        }
+    }
+
+    debug_assert!(syn::parse_file(code).is_ok());
+
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::strip_comments;
+    use crate::testing::tempdir_with_files;
+    use camino::Utf8Path;
+    use indoc::indoc;
+
+    /// Writes `files` to a temporary directory and expands `mod`s in `lib.rs` in it.
+    fn expand_mods(files: &[(&str, &str)]) -> anyhow::Result<String> {
+        let tempdir = tempdir_with_files(files)?;
+        let dir = Utf8Path::from_path(tempdir.path()).unwrap();
+        super::expand_mods(&dir.join("lib.rs"), None, 4).map_err(anyhow::Error::msg)
+    }
+
+    #[test]
+    fn expand_mods_non_mod_rs() -> anyhow::Result<()> {
+        assert_eq!(
+            indoc! {"
+                mod a {
+                    mod c {
+                        fn c() {}
+                    }
+                }
+            "},
+            expand_mods(&[
+                ("lib.rs", "mod a;\n"),
+                ("a.rs", "mod c;\n"),
+                ("a/c.rs", "fn c() {}\n"),
+            ])?,
+        );
+        // Not searched next to `a.rs`, on any edition.
+        assert!(expand_mods(&[
+            ("lib.rs", "mod a;\n"),
+            ("a.rs", "mod b;\n"),
+            ("b.rs", "fn b() {}\n"),
+        ])
+        .is_err());
+        Ok(())
+    }
 
-        debug_assert!(syn::parse_file(&code).is_ok());
+    #[test]
+    fn expand_mods_crlf() -> anyhow::Result<()> {
+        let code = expand_mods(&[
+            ("lib.rs", "mod a;\r\nmod b;\r\nfn f() {}\r\n"),
+            ("a.rs", "fn a() {}\r\n"),
+            ("b.rs", "fn b() {}\r\n"),
+        ])?;
+        assert_eq!(
+            indoc! {"
+                mod a {
+                    fn a() {}
+                }
+                mod b {
+                    fn b() {}
+                }
+                fn f() {}
+            "},
+            code,
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn expand_mods_bom() -> anyhow::Result<()> {
+        let code = expand_mods(&[
+            ("lib.rs", "\u{feff}mod a;\n"),
+            ("a.rs", "\u{feff}fn a() {}\n"),
+        ])?;
+        // The code sizes are measured with this.
+        assert_eq!("mod a {\n    fn a() {}\n}\n", code);
+        Ok(())
+    }
+
+    #[test]
+    fn main_fn_lines_bom_crlf() -> anyhow::Result<()> {
+        let tempdir = tempdir_with_files(&[(
+            "main.rs",
+            "\u{feff}use std::io;\r\n\r\nfn main() {\r\n}\r\n",
+        )])?;
+        let path = Utf8Path::from_path(tempdir.path()).unwrap().join("main.rs");
+        assert_eq!(Ok(Some((3, 4))), super::main_fn_lines(&path));
+        Ok(())
+    }
+
+    #[test]
+    fn expand_mods_path_to_dir() -> anyhow::Result<()> {
+        let code = expand_mods(&[
+            ("lib.rs", "#[path = \"subdir\"]\nmod a;\n"),
+            ("subdir/mod.rs", "mod b;\n"),
+            ("subdir/b.rs", "fn b() {}\n"),
+        ])?;
+        assert_eq!(
+            indoc! {r#"
+                #[path = "subdir"]
+                mod a {
+                    mod b {
+                        fn b() {}
+                    }
+                }
+            "#},
+            code,
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn expand_mods_in_inline_mod() -> anyhow::Result<()> {
+        let code = expand_mods(&[
+            ("lib.rs", "pub mod detail {\n    mod impl_;\n}\n"),
+            ("detail/impl_.rs", "fn f() {}\n"),
+        ])?;
+        assert_eq!(
+            "pub mod detail {\n    mod impl_ {\n        fn f() {}\n    }\n}\n",
+            code,
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn strip_comments_raw_strings() {
+        assert_eq!(
+            r####"let s = r#"#abc"#;  f();"####,
+            strip_comments(r####"let s = r#"#abc"#; /* c */f();"####),
+        );
+        assert_eq!(
+            r####"let s = r##"// "# /*"##;"####,
+            strip_comments(r####"let s = r##"// "# /*"##;"####),
+        );
+        assert_eq!(
+            r####"let s = br"//";"####,
+            strip_comments(r####"let s = br"//"; // c"####).trim_end(),
+        );
+    }
+
+    #[test]
+    fn strip_comments_nested_block_comments() {
+        assert_eq!("a   b", strip_comments("a /* x /* y */ z */ b"));
+        assert_eq!("a   b", strip_comments("a /** doc /* */ */ b"));
+    }
 
-        ret
+    #[test]
+    fn strip_comments_string_literals() {
+        assert_eq!(
+            r#"let s = "// not a comment";"#,
+            strip_comments(r#"let s = "// not a comment"; // a comment"#).trim_end(),
+        );
+        assert_eq!(
+            r#"let s = "\" /* */";"#,
+            strip_comments(r#"let s = "\" /* */";"#),
+        );
+        assert_eq!("let c = '\"'; ", strip_comments("let c = '\"'; // \""));
+        assert_eq!(
+            "fn f<'a>() {}\n",
+            strip_comments("/// doc\nfn f<'a>() {}\n").trim_start()
+        );
     }
 }
@@ -1,20 +1,40 @@
 use std::{
-    fmt,
+    env, fmt,
     io::{self, Write},
+    str::FromStr,
 };
 use termcolor::{Color, ColorSpec, StandardStream, WriteColor};
 
 pub struct Shell {
     output: ShellOut,
+    verbosity: Verbosity,
+    /// Width of the progress bar currently shown.
+    progress_width: Option<usize>,
 }
 
 impl Shell {
     pub fn new() -> Self {
         Self {
             output: ShellOut::stream(),
+            verbosity: Verbosity::Normal,
+            progress_width: None,
         }
     }
 
+    pub fn set_color_choice(&mut self, color: ColorChoice) {
+        self.output = ShellOut::stream_with_color(color);
+    }
+
+    /// With [`Verbosity::Quiet`], suppresses the status messages and keeps stdout for the output of
+    /// this program itself.
+    pub fn set_verbosity(&mut self, verbosity: Verbosity) {
+        self.verbosity = verbosity;
+    }
+
+    pub(crate) fn is_quiet(&self) -> bool {
+        self.verbosity == Verbosity::Quiet
+    }
+
     pub(crate) fn out(&mut self) -> &mut dyn Write {
         let ShellOut::Stream { stdout, .. } = &mut self.output;
         stdout
@@ -30,9 +50,64 @@ impl Shell {
         status: impl fmt::Display,
         message: impl fmt::Display,
     ) -> io::Result<()> {
+        self.status_with_verbosity(Verbosity::Normal, status, message)
+    }
+
+    /// Prints a status message if the verbosity is `verbosity` or higher.
+    pub(crate) fn status_with_verbosity(
+        &mut self,
+        verbosity: Verbosity,
+        status: impl fmt::Display,
+        message: impl fmt::Display,
+    ) -> io::Result<()> {
+        if self.verbosity < verbosity {
+            return Ok(());
+        }
         self.print(status, message, Color::Green, true)
     }
 
+    /// Shows a progress bar in place of the last one, if the verbosity is
+    /// [`Verbosity::Normal`] and stderr is a TTY.
+    pub(crate) fn progress(
+        &mut self,
+        status: impl fmt::Display,
+        current: usize,
+        total: usize,
+    ) -> io::Result<()> {
+        const WIDTH: usize = 40;
+
+        if self.verbosity != Verbosity::Normal || !atty::is(atty::Stream::Stderr) {
+            return Ok(());
+        }
+        let filled = (WIDTH * current).checked_div(total).unwrap_or(WIDTH);
+        let bar = format!(
+            "[{}{}] {}/{}",
+            "=".repeat(filled),
+            " ".repeat(WIDTH - filled),
+            current,
+            total,
+        );
+        let ShellOut::Stream { stderr, .. } = &mut self.output;
+        write!(stderr, "\r")?;
+        stderr.set_color(ColorSpec::new().set_bold(true).set_fg(Some(Color::Cyan)))?;
+        write!(stderr, "{:>12}", status)?;
+        stderr.reset()?;
+        write!(stderr, " {}", bar)?;
+        stderr.flush()?;
+        self.progress_width = Some(13 + bar.len());
+        Ok(())
+    }
+
+    /// Erases the progress bar, if any.
+    pub(crate) fn clear_progress(&mut self) -> io::Result<()> {
+        if let Some(width) = self.progress_width.take() {
+            let ShellOut::Stream { stderr, .. } = &mut self.output;
+            write!(stderr, "\r{}\r", " ".repeat(width))?;
+            stderr.flush()?;
+        }
+        Ok(())
+    }
+
     pub(crate) fn warn(&mut self, message: impl fmt::Display) -> io::Result<()> {
         self.print("warning", message, Color::Yellow, false)
     }
@@ -77,17 +152,77 @@ enum ShellOut {
 
 impl ShellOut {
     fn stream() -> Self {
-        Self::Stream {
-            stdout: StandardStream::stdout(if atty::is(atty::Stream::Stdout) {
-                termcolor::ColorChoice::Auto
-            } else {
-                termcolor::ColorChoice::Never
-            }),
-            stderr: StandardStream::stderr(if atty::is(atty::Stream::Stderr) {
+        Self::stream_with_color(ColorChoice::Auto)
+    }
+
+    fn stream_with_color(color: ColorChoice) -> Self {
+        let choice = |stream| match color {
+            ColorChoice::Auto if atty::is(stream) && env::var_os("NO_COLOR").is_none() => {
                 termcolor::ColorChoice::Auto
-            } else {
-                termcolor::ColorChoice::Never
-            }),
+            }
+            ColorChoice::Auto | ColorChoice::Never => termcolor::ColorChoice::Never,
+            ColorChoice::Always => termcolor::ColorChoice::Always,
+        };
+        Self::Stream {
+            stdout: StandardStream::stdout(choice(atty::Stream::Stdout)),
+            stderr: StandardStream::stderr(choice(atty::Stream::Stderr)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// No status messages.
+    Quiet,
+    Normal,
+    /// Prints each of the copied files.
+    Verbose,
+    /// Also prints the commands run silently.
+    VeryVerbose,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub const VARIANTS: &'static [&'static str] = &["auto", "always", "never"];
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            s => Err(format!("expected one of {:?}, got {:?}", Self::VARIANTS, s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Human,
+    Json,
+}
+
+impl OutputFormat {
+    pub const VARIANTS: &'static [&'static str] = &["human", "json"];
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Self::Human),
+            "json" => Ok(Self::Json),
+            s => Err(format!("expected one of {:?}, got {:?}", Self::VARIANTS, s)),
         }
     }
 }
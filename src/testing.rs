@@ -0,0 +1,28 @@
+use std::{fs, path::Path};
+use tempfile::TempDir;
+
+/// Creates an empty temporary directory.
+pub(crate) fn tempdir() -> anyhow::Result<TempDir> {
+    let tempdir = tempfile::Builder::new()
+        .prefix("cargo-cpl-test-")
+        .tempdir()?;
+    Ok(tempdir)
+}
+
+/// Creates a temporary directory with `files`, which are pairs of the relative paths and the
+/// contents.
+pub(crate) fn tempdir_with_files(files: &[(&str, &str)]) -> anyhow::Result<TempDir> {
+    let tempdir = tempdir()?;
+    write_files(tempdir.path(), files)?;
+    Ok(tempdir)
+}
+
+/// Writes `files` in `dir`, creating the parent directories.
+pub(crate) fn write_files(dir: &Path, files: &[(&str, &str)]) -> anyhow::Result<()> {
+    for (path, content) in files {
+        let path = dir.join(path);
+        fs::create_dir_all(path.with_file_name(""))?;
+        fs::write(path, content)?;
+    }
+    Ok(())
+}
@@ -1,98 +1,615 @@
 use crate::{
-    github, process_builder,
-    shell::Shell,
-    workspace::{self, PackageExt as _, TargetExt as _},
+    cache::{self, Checkpoints, Timestamps},
+    git, github,
+    process_builder::{self, Present, ProcessBuilder, TimedOut},
+    shell::{OutputFormat, Shell, Verbosity},
+    workspace::{
+        self, normal_deps, MetadataExt as _, PackageExt as _, PackageMetadataCargoCpl,
+        TargetExt as _,
+    },
 };
-use anyhow::{anyhow, Context as _};
+use anyhow::{anyhow, bail, Context as _};
 use camino::Utf8Path;
 use cargo_metadata as cm;
 use git2::Repository;
-use ignore::Walk;
+use ignore::{overrides::OverrideBuilder, WalkBuilder};
+use indexmap::IndexMap;
 use indoc::indoc;
 use itertools::Itertools as _;
-use maplit::{btreemap, btreeset};
-use serde::Deserialize;
+use maplit::{btreemap, hashmap, hashset};
+use notify::{DebouncedEvent, RecursiveMode, Watcher as _};
+use rayon::iter::{IntoParallelRefIterator as _, ParallelIterator as _};
+use serde::{Deserialize, Serialize};
 use serde_json::json;
+use sha2::{Digest as _, Sha256};
 use std::{
     collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    env, fs, io, iter,
     path::{Path, PathBuf},
+    process,
+    rc::Rc,
+    str::FromStr,
+    sync::{
+        atomic::{self, AtomicBool},
+        mpsc::{self, RecvTimeoutError},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
 };
 use url::Url;
 
+pub struct VerifyForGhPagesOptions<'a> {
+    /// `nightly` toolchain. If `None`, `toolchain` in `[workspace.metadata.cargo-cpl]`, the channel
+    /// in `rust-toolchain.toml`, or `nightly` is used.
+    pub nightly_toolchain: Option<&'a str>,
+    pub open: bool,
+    pub open_crate: Option<&'a str>,
+    pub dirty: bool,
+    pub watch: bool,
+    pub show_examples: bool,
+    pub show_dev_deps: bool,
+    pub remote: Option<&'a str>,
+    pub github_host: Option<&'a str>,
+    pub rev: Option<&'a str>,
+    pub format: OutputFormat,
+    pub jobs: Option<usize>,
+    pub keep_going: bool,
+    pub force: bool,
+    pub no_test: bool,
+    pub skip_udeps: bool,
+    pub packages: &'a [String],
+    pub out_dir: Option<&'a Path>,
+    pub offline: bool,
+    pub locked: bool,
+    pub frozen: bool,
+    pub no_metadata_cache: bool,
+    pub emit_summary: bool,
+    pub docs_base_url: Option<&'a Url>,
+    pub retries: u32,
+    pub test_timeout: Option<Duration>,
+    pub dry_run: bool,
+    pub changed_since: Option<&'a str>,
+    pub offline_assets: bool,
+    pub toc_sort: TocSort,
+    pub toc_depth: Option<usize>,
+    pub deny_unverified: bool,
+    pub allow_dep_cycles: bool,
+    /// Globs of the paths not to copy to the workspace for the docs, in addition to `target` and
+    /// `.git`.
+    pub copy_exclude: &'a [String],
+    /// Copy the whole repository to the workspace for the docs, instead of only the packages to
+    /// document and their path dependencies.
+    pub copy_whole_repo: bool,
+    pub copy_mode: CopyMode,
+    /// Warn about the path dependencies in the repository reported unused by `cargo udeps`.
+    pub warn_unused: bool,
+    /// Only analyze the workspace of the manifest, instead of all of the workspaces in the
+    /// repository.
+    pub manifest_path: Option<&'a Path>,
+    /// Only analyze the workspace containing `cwd`. Ignored if `manifest_path` is `Some`.
+    pub current_workspace: bool,
+    /// Send a `HEAD` request to each problem URL with `curl` before the tests, warning about the
+    /// unreachable ones.
+    pub check_problem_urls: bool,
+    /// Write `competitive-verifier-result.json` to the doc directory.
+    pub emit_cverifier: bool,
+    /// File to record when each test passed in, to skip the tests passed with the same sources.
+    pub timestamps: Option<&'a Path>,
+    /// Re-run the tests recorded in `timestamps` as passed more than this long ago.
+    pub max_age: Option<Duration>,
+}
+
+/// Order of the crates in each folder of the table of contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TocSort {
+    /// Alphabetical.
+    Name,
+    /// Verified crates first, then alphabetical.
+    VerifiedFirst,
+}
+
+impl TocSort {
+    pub const VARIANTS: &'static [&'static str] = &["name", "verified-first"];
+}
+
+impl FromStr for TocSort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(Self::Name),
+            "verified-first" => Ok(Self::VerifiedFirst),
+            s => Err(format!("expected one of {:?}, got {:?}", Self::VARIANTS, s)),
+        }
+    }
+}
+
+/// How to copy the files to the workspace for the docs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyMode {
+    Copy,
+    /// Hardlink the files, copying them if it fails (e.g. across filesystems).
+    Hardlink,
+}
+
+impl CopyMode {
+    pub const VARIANTS: &'static [&'static str] = &["copy", "hardlink"];
+}
+
+impl FromStr for CopyMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "copy" => Ok(Self::Copy),
+            "hardlink" => Ok(Self::Hardlink),
+            s => Err(format!("expected one of {:?}, got {:?}", Self::VARIANTS, s)),
+        }
+    }
+}
+
 pub fn verify_for_gh_pages(
-    nightly_toolchain: &str,
-    open: bool,
+    opts: VerifyForGhPagesOptions<'_>,
     cwd: &Path,
     shell: &mut Shell,
 ) -> anyhow::Result<()> {
+    let VerifyForGhPagesOptions {
+        nightly_toolchain,
+        open,
+        open_crate,
+        dirty,
+        watch,
+        show_examples,
+        show_dev_deps,
+        remote,
+        github_host,
+        rev,
+        format,
+        jobs,
+        keep_going,
+        force,
+        no_test,
+        skip_udeps,
+        packages,
+        out_dir,
+        offline,
+        locked,
+        frozen,
+        no_metadata_cache,
+        emit_summary,
+        docs_base_url,
+        retries,
+        test_timeout,
+        dry_run,
+        changed_since,
+        offline_assets,
+        toc_sort,
+        toc_depth,
+        deny_unverified,
+        allow_dep_cycles,
+        copy_exclude,
+        copy_whole_repo,
+        copy_mode,
+        warn_unused,
+        manifest_path,
+        current_workspace,
+        check_problem_urls,
+        emit_cverifier,
+        timestamps: timestamps_path,
+        max_age,
+    } = opts;
+
+    let last_verified = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .with_context(|| "the system clock is set before 1970")?;
+
+    if format == OutputFormat::Json {
+        shell.set_verbosity(Verbosity::Quiet);
+    }
+
     let repo = &Repository::discover(cwd)?;
     let repo_workdir = repo.workdir().expect("this is constructed with `discover`");
 
-    let (gh_username, gh_repo_name, gh_branch_name) = github::remote(repo)?;
-    let rev = github::rev(repo)?;
-
-    let gh_url = format!("https://github.com/{}/{}", gh_username, gh_repo_name);
-    let gh_url = &gh_url
-        .parse::<Url>()
-        .with_context(|| format!("invalid URL: {}", gh_url))?;
-
-    let gh_blob_url = |rel_filepath: &Utf8Path| -> Url {
-        let mut url = gh_url.clone();
-        let mut path_segments = url.path_segments_mut().expect("this is `https://`");
-        path_segments.push("blob");
-        path_segments.push(&rev.to_string());
-        path_segments.extend(rel_filepath);
-        drop(path_segments);
-        url
+    let github::Remote { host: vcs_host, .. } = github::remote(repo, remote, github_host)?;
+    let rev = &if let Some(rev) = rev {
+        github::resolve_rev(repo, rev)?
+    } else {
+        github::rev(repo)?.to_string()
+    };
+
+    let gh_url = &vcs_host.repo_url();
+    let gh_blob_url = |rel_filepath: &Utf8Path, lines| vcs_host.blob_url(rev, rel_filepath, lines);
+
+    let cargo_flags = &[
+        (offline, "--offline"),
+        (locked, "--locked"),
+        (frozen, "--frozen"),
+    ]
+    .iter()
+    .filter(|&&(enabled, _)| enabled)
+    .map(|&(_, flag)| flag)
+    .collect::<Vec<_>>();
+
+    let doc_ws = &match out_dir {
+        Some(out_dir) => cwd.join(out_dir),
+        None => cache::cache_dir()?.join("workspace"),
+    };
+    // The workspace for the docs is generated by us, so its `Cargo.lock` does not exist until the
+    // first run.
+    if (locked || frozen) && !dry_run && !doc_ws.join("Cargo.lock").exists() {
+        bail!(
+            "`{}` does not exist yet. run without `--locked` and `--frozen` once to generate it",
+            doc_ws.join("Cargo.lock").display(),
+        );
+    }
+
+    let manifest_path = match (manifest_path, current_workspace) {
+        (Some(manifest_path), _) => Some(
+            dunce::canonicalize(cwd.join(manifest_path))
+                .with_context(|| format!("could not find `{}`", manifest_path.display()))?,
+        ),
+        (None, true) => Some(dunce::canonicalize(workspace::locate_project(cwd)?)?),
+        (None, false) => None,
+    };
+    let metadata_list = if let Some(manifest_path) = &manifest_path {
+        if !manifest_path.starts_with(repo_workdir) {
+            bail!("`{}` is outside of the repository", manifest_path.display());
+        }
+        workspace::single_workspace_metadata(manifest_path, cargo_flags, !no_metadata_cache)?
+    } else {
+        workspace::list_metadata(repo_workdir, cargo_flags, !no_metadata_cache, jobs)?
+    };
+
+    // `[workspace.metadata.cargo-cpl]` of the workspace nearest to the root of the repository.
+    let config = metadata_list
+        .values()
+        .min_by_key(|m| (m.workspace_root.components().count(), &m.workspace_root))
+        .map(|m| m.workspace_metadata())
+        .transpose()?
+        .unwrap_or_default()
+        .cargo_cpl;
+    let docs_base_url = docs_base_url.or(config.docs_base_url.as_ref());
+    let copy_exclude = if copy_exclude.is_empty() {
+        &config.copy_exclude
+    } else {
+        copy_exclude
     };
+    let deny_unverified = deny_unverified || config.deny_unverified;
 
-    let metadata_list = workspace::list_metadata(repo_workdir)?;
+    if let Some(cycle) = workspace::find_dep_cycle(&metadata_list, false) {
+        let msg = format!("circular path dependencies: {}", cycle.join(" -> "));
+        if !allow_dep_cycles {
+            bail!("{}", msg);
+        }
+        shell.warn(msg)?;
+    } else if let Some(cycle) = workspace::find_dep_cycle(&metadata_list, true) {
+        shell.warn(format!(
+            "circular path dependencies through dev-dependencies: {}",
+            cycle.join(" -> "),
+        ))?;
+    }
+
+    let nightly_toolchain = nightly_toolchain.or(config.toolchain.as_deref());
+    let toolchain_file_channel = if nightly_toolchain.is_none() {
+        workspace::toolchain_file_channel(repo_workdir)?
+    } else {
+        None
+    };
+    let nightly_toolchain = nightly_toolchain
+        .or(toolchain_file_channel.as_deref())
+        .unwrap_or("nightly");
+
+    let docs_base_url = &match docs_base_url {
+        Some(url) if url.cannot_be_a_base() => {
+            bail!("`{}` cannot be used as the base URL of the docs", url);
+        }
+        Some(url) if !url.path().ends_with('/') => {
+            let mut url = url.clone();
+            url.set_path(&format!("{}/", url.path()));
+            url
+        }
+        Some(url) => url.clone(),
+        None => "https://docs.rs/".parse().unwrap(),
+    };
+    let doc_opts = &DocOptions {
+        nightly_toolchain,
+        cargo_flags,
+        docs_base_url,
+        offline_assets,
+        toc_sort,
+        toc_depth,
+        copy_exclude,
+        copy_whole_repo,
+        copy_mode,
+        jobs,
+    };
+
+    let mut filters = vec![];
+    if !packages.is_empty() {
+        filters.push((
+            specified_packages(packages, &metadata_list)?,
+            "not specified with `--package`",
+        ));
+    }
+    if dirty {
+        filters.push((
+            affected_packages(&git::dirty_files(repo)?, &metadata_list)?,
+            "not affected by the uncommitted changes",
+        ));
+    }
+    if let Some(changed_since) = changed_since {
+        filters.push((
+            affected_packages(
+                &git::files_changed_since(repo, changed_since)?,
+                &metadata_list,
+            )?,
+            "not affected by the changes since the revision",
+        ));
+    }
+    let deselection_reason = |id: &cm::PackageId| {
+        filters
+            .iter()
+            .find(|(selected, _)| !selected.contains(id))
+            .map(|&(_, reason)| reason)
+    };
+    let is_selected = |id: &cm::PackageId| deselection_reason(id).is_none();
 
     let cargo_exes = metadata_list
         .values()
         .map(|m| &m.workspace_root)
         .unique()
         .map(|workspace_root| {
-            let cargo_exe = process_builder::process("rustup")
+            if let Some(cargo_exe) = env::var_os("CARGO") {
+                return Ok((workspace_root, cargo_exe));
+            }
+            if !process_builder::rustup_installed() {
+                return Ok((workspace_root, "cargo".into()));
+            }
+            let which_cargo = process_builder::process("rustup")
                 .args(&["which", "cargo"])
-                .cwd(workspace_root)
-                .read(true)?;
-            Ok((workspace_root, cargo_exe))
+                .cwd(workspace_root);
+            shell.status_with_verbosity(Verbosity::VeryVerbose, "Running", &which_cargo)?;
+            let cargo_exe = which_cargo.read(true)?;
+            Ok((workspace_root, cargo_exe.into()))
+        })
+        .collect::<anyhow::Result<HashMap<_, _>>>()?;
+
+    // Versions of the toolchains the tests are run with, which the checkpoints depend on.
+    let cargo_versions = metadata_list
+        .values()
+        .map(|m| &m.workspace_root)
+        .unique()
+        .filter(|_| !(watch || no_test || dry_run))
+        .map(|workspace_root| {
+            let cargo_version = process_builder::process(&cargo_exes[workspace_root])
+                .arg("-V")
+                .cwd(workspace_root);
+            shell.status_with_verbosity(Verbosity::VeryVerbose, "Running", &cargo_version)?;
+            Ok((workspace_root, cargo_version.read(true)?))
+        })
+        .collect::<anyhow::Result<HashMap<_, _>>>()?;
+
+    let test_commands = metadata_list
+        .values()
+        .unique_by(|m| &m.workspace_root)
+        .map(|m| {
+            let test_command = m.workspace_metadata()?.cargo_cpl.test_command;
+            Ok((&m.workspace_root, test_command))
         })
         .collect::<anyhow::Result<HashMap<_, _>>>()?;
 
     let bin_metadata = metadata_list
         .iter()
         .map(|(ws_member, metadata)| {
-            let package_metadata = metadata[ws_member].metadata()?;
-            Ok((ws_member, package_metadata.cargo_compete.bin))
+            let ws_member = &metadata[ws_member];
+            let bin = ws_member.bin_problems(shell)?;
+            Ok((&ws_member.id, bin))
         })
         .collect::<anyhow::Result<HashMap<_, _>>>()?;
 
+    let cargo_compete_workspace_roots = metadata_list
+        .iter()
+        .filter(|(ws_member, metadata)| {
+            !(watch || no_test || dry_run)
+                && is_selected(ws_member)
+                && !bin_metadata[ws_member].is_empty()
+                && test_commands[&metadata.workspace_root].is_none()
+        })
+        .map(|(_, metadata)| &metadata.workspace_root)
+        .unique();
+    for workspace_root in cargo_compete_workspace_roots {
+        let compete_installed = process_builder::process(&cargo_exes[workspace_root])
+            .arg("compete")
+            .arg("--version")
+            .cwd(workspace_root)
+            .status_silent(shell)?
+            .success();
+        if !compete_installed {
+            bail!(
+                "`cargo-compete` is not available for `{}`. install it with `cargo install \
+                 cargo-compete --locked`, or pass `--no-test`",
+                workspace_root,
+            );
+        }
+    }
+
+    // The test commands do not take `--locked` and `--frozen`, so `Cargo.lock` is checked here.
+    if locked || frozen {
+        let tested_workspace_roots = metadata_list
+            .iter()
+            .filter(|(ws_member, _)| {
+                !(watch || no_test || dry_run)
+                    && is_selected(ws_member)
+                    && !bin_metadata[ws_member].is_empty()
+            })
+            .map(|(_, metadata)| &metadata.workspace_root)
+            .unique();
+        for workspace_root in tested_workspace_roots {
+            process_builder::process(&cargo_exes[workspace_root])
+                .arg("fetch")
+                .args(cargo_flags)
+                .cwd(workspace_root)
+                .exec_with_status(shell)?;
+        }
+    }
+
+    if check_problem_urls {
+        let curl_installed = matches!(
+            process_builder::process("curl")
+                .arg("--version")
+                .cwd(repo_workdir)
+                .status_silent(shell),
+            Ok(status) if status.success()
+        );
+        if !curl_installed {
+            bail!("`curl` is required for `--check-problem-urls`");
+        }
+
+        let problem_urls = bin_metadata
+            .iter()
+            .filter(|(id, _)| is_selected(id))
+            .flat_map(|(_, bins)| bins.values())
+            .collect::<BTreeSet<_>>();
+        let mut unreachable = vec![];
+        for (i, url) in problem_urls.iter().enumerate() {
+            shell.progress("Checking", i, problem_urls.len())?;
+            // The headers are discarded, since they may not be UTF-8.
+            let curl = process_builder::process("curl")
+                .args(&["--head", "--location", "--silent", "--show-error", "--fail"])
+                .args(&["--output", if cfg!(windows) { "NUL" } else { "/dev/null" }])
+                .args(&["--max-time", "30", url.as_str()])
+                .cwd(repo_workdir);
+            shell.status_with_verbosity(Verbosity::VeryVerbose, "Running", &curl)?;
+            if let Err(err) = curl.read_captured(true) {
+                unreachable.push((url, err));
+            }
+        }
+        shell.clear_progress()?;
+        shell.status(
+            "Checked",
+            format!(
+                "{} problem URL(s) ({} unreachable)",
+                problem_urls.len(),
+                unreachable.len(),
+            ),
+        )?;
+        for (url, err) in unreachable {
+            shell.warn(format!("`{}` is unreachable: {}", url, err.root_cause()))?;
+        }
+    }
+
+    let mut timings = Timings::default();
+
+    let started = Instant::now();
+    let udeps_ws_members = metadata_list
+        .iter()
+        .filter(|(ws_member, _)| {
+            // The watch mode is for previewing the docs, so it skips the slow `cargo udeps`.
+            !(skip_udeps || dry_run || watch || bin_metadata[ws_member].is_empty())
+        })
+        .map(|(ws_member, metadata)| (&metadata[ws_member], metadata))
+        .collect::<Vec<_>>();
+    for workspace_root in udeps_ws_members
+        .iter()
+        .map(|(_, metadata)| &metadata.workspace_root)
+        .unique()
+    {
+        let udeps_installed = process_builder::toolchain_cargo(nightly_toolchain)
+            .arg("udeps")
+            .arg("--version")
+            .cwd(workspace_root)
+            .status_silent(shell)?
+            .success();
+        if !udeps_installed {
+            bail!(
+                "`cargo-udeps` is not available for `{}`. install it with `cargo +{} install \
+                 cargo-udeps --locked`, or pass `--skip-udeps`",
+                nightly_toolchain,
+                nightly_toolchain,
+            );
+        }
+    }
+    // `cargo udeps` merges the targets of a package, so it is run for each bin.
+    let unused_normal_deps = udeps_ws_members
+        .iter()
+        .flat_map(|&(ws_member, metadata)| {
+            bin_metadata[&ws_member.id]
+                .keys()
+                .map(move |bin_name| (ws_member, metadata, bin_name))
+        })
+        .map(|(ws_member, metadata, bin_name)| {
+            let cargo_udeps_output = &process_builder::toolchain_cargo(nightly_toolchain)
+                .arg("udeps")
+                .arg("--manifest-path")
+                .arg(&ws_member.manifest_path)
+                .arg("--bin")
+                .arg(bin_name)
+                .arg("--output")
+                .arg("json")
+                .args(cargo_flags)
+                .cwd(&metadata.workspace_root)
+                .tee_with_status(false, shell)?;
+
+            let unused_normal_names_in_toml =
+                serde_json::from_str::<CargoUdepsOutput>(cargo_udeps_output)?
+                    .unused_deps
+                    .into_iter()
+                    .find(|(_, CargoUdepsOutputDeps { manifest_path, .. })| {
+                        *manifest_path == ws_member.manifest_path
+                    })
+                    .map(|(_, CargoUdepsOutputDeps { normal, .. })| normal)
+                    .unwrap_or_default();
+            Ok(((&ws_member.id, &**bin_name), unused_normal_names_in_toml))
+        })
+        .collect::<anyhow::Result<HashMap<_, _>>>()?;
+    timings.add("udeps", started);
+
+    let unused_path_deps = metadata_list
+        .iter()
+        .filter(|_| warn_unused)
+        .flat_map(|(ws_member, metadata)| {
+            let ws_member = &metadata[ws_member];
+            // Unused if none of the bins use it.
+            let unused_normal_names_in_toml = bin_metadata[&ws_member.id]
+                .keys()
+                .map(|bin_name| unused_normal_deps.get(&(&ws_member.id, &**bin_name)))
+                .collect::<Option<Vec<_>>>()?;
+            if unused_normal_names_in_toml.is_empty() {
+                return None;
+            }
+            let unused_path_deps = ws_member
+                .dependencies
+                .iter()
+                .filter(|dep| {
+                    dep.kind == cm::DependencyKind::Normal
+                        && matches!(&dep.path, Some(path) if path.starts_with(repo_workdir))
+                })
+                .map(|dep| dep.rename.as_ref().unwrap_or(&dep.name))
+                .filter(move |name_in_toml| {
+                    unused_normal_names_in_toml
+                        .iter()
+                        .all(|names| names.contains(*name_in_toml))
+                })
+                .map(move |name_in_toml| (&ws_member.name, name_in_toml));
+            Some(unused_path_deps)
+        })
+        .flatten()
+        .collect::<Vec<_>>();
+
+    let mut skipped = Skipped::default();
+
     let mut verifications: BTreeMap<_, BTreeSet<_>> = btreemap!();
+    let mut examples: HashMap<_, BTreeSet<_>> = hashmap!();
+    let mut source_hashes = hashmap!();
+    // Problems of the solution files, keyed with the paths relative to the repository, and whether
+    // they are tested in this run.
+    let mut solution_files: BTreeMap<_, (BTreeSet<_>, _)> = btreemap!();
 
     for (ws_member, metadata) in &metadata_list {
         let ws_member = &metadata[ws_member];
 
-        let normal_deps = &metadata
-            .resolve
-            .as_ref()
-            .unwrap()
-            .nodes
-            .iter()
-            .map(|cm::Node { id, deps, .. }| {
-                let deps = deps
-                    .iter()
-                    .filter(|cm::NodeDep { dep_kinds, .. }| {
-                        dep_kinds
-                            .iter()
-                            .any(|cm::DepKindInfo { kind, .. }| *kind == cm::DependencyKind::Normal)
-                    })
-                    .map(|cm::NodeDep { name, pkg, .. }| (name, pkg))
-                    .collect::<Vec<_>>();
-                (id, deps)
-            })
-            .collect::<HashMap<_, _>>();
+        let normal_deps = &normal_deps(metadata);
 
         let explicit_names_in_toml = ws_member
             .dependencies
@@ -133,47 +650,35 @@ pub fn verify_for_gh_pages(
                             bin_target.src_path,
                         )
                     })?;
-                (problem_url, gh_blob_url(Utf8Path::new(&relative_src_path)))
+                let main_fn_lines = crate::rust::main_fn_lines(&bin_target.src_path)
+                    .ok()
+                    .flatten();
+                let (problems, tested) = solution_files
+                    .entry(relative_src_path.replace('\\', "/"))
+                    .or_default();
+                problems.insert(problem_url);
+                *tested = !(watch || no_test) && is_selected(&ws_member.id);
+                (
+                    problem_url,
+                    gh_blob_url(Utf8Path::new(&relative_src_path), main_fn_lines),
+                )
             };
 
-            let cargo_udeps_output = &process_builder::process("rustup")
-                .arg("run")
-                .arg(nightly_toolchain)
-                .arg("cargo")
-                .arg("udeps")
-                .arg("--manifest-path")
-                .arg(&ws_member.manifest_path)
-                .arg("--bin")
-                .arg(bin_name)
-                .arg("--output")
-                .arg("json")
-                .cwd(&metadata.workspace_root)
-                .read_with_status(false, shell)?;
-
-            let unused_normal_names_in_toml =
-                serde_json::from_str::<CargoUdepsOutput>(cargo_udeps_output)?
-                    .unused_deps
-                    .into_iter()
-                    .find(|(_, CargoUdepsOutputDeps { manifest_path, .. })| {
-                        *manifest_path == ws_member.manifest_path
-                    })
-                    .map(|(_, CargoUdepsOutputDeps { normal, .. })| normal)
-                    .unwrap_or_default();
+            let unused_normal_names_in_toml = &unused_normal_deps
+                .get(&(&ws_member.id, &**bin_name))
+                .cloned()
+                .unwrap_or_default();
 
             let deps_in_same_repo = {
-                let mut deps = btreeset!();
-                let stack = &mut normal_deps_depth1
-                    .iter()
-                    .filter(|&(name_in_toml, _)| {
-                        !unused_normal_names_in_toml.contains(*name_in_toml)
-                    })
-                    .map(|(_, package_id)| *package_id)
-                    .collect::<Vec<_>>();
-                while let Some(package_id) = stack.pop() {
-                    if deps.insert(package_id) {
-                        stack.extend(normal_deps[package_id].iter().map(|(_, pkg)| *pkg));
-                    }
-                }
+                let deps = workspace::transitive_normal_deps(
+                    normal_deps,
+                    normal_deps_depth1
+                        .iter()
+                        .filter(|&(name_in_toml, _)| {
+                            !unused_normal_names_in_toml.contains(*name_in_toml)
+                        })
+                        .map(|(_, package_id)| *package_id),
+                );
                 deps.into_iter()
                     .flat_map(|id| {
                         let package = &metadata[id];
@@ -182,18 +687,46 @@ pub fn verify_for_gh_pages(
                             .or_else(|| package.proc_macro_target())?;
                         match dunce::canonicalize(src_path) {
                             Ok(src_path) if src_path.starts_with(repo_workdir) => Some(Ok(id)),
-                            Ok(_) => None,
+                            Ok(_) => {
+                                if package.source.is_none() {
+                                    skipped.insert(package, "outside of the repository");
+                                }
+                                None
+                            }
                             Err(err) => Some(Err(err)),
                         }
                     })
                     .collect::<Result<Vec<_>, _>>()?
             };
 
+            source_hashes.insert(
+                (&ws_member.id, bin_name),
+                source_hash(
+                    iter::once((ws_member, bin_target)).chain(deps_in_same_repo.iter().flat_map(
+                        |id| {
+                            let package = &metadata[*id];
+                            let krate = package
+                                .lib_target()
+                                .or_else(|| package.proc_macro_target())?;
+                            Some((package, krate))
+                        },
+                    )),
+                    ws_member,
+                    &metadata.workspace_root,
+                    test_commands[&metadata.workspace_root].as_deref(),
+                    cargo_versions.get(&metadata.workspace_root).map(|s| &**s),
+                ),
+            );
+
             for dep_in_same_repo in deps_in_same_repo {
                 verifications
                     .entry(dep_in_same_repo)
                     .or_default()
                     .insert(verification.clone());
+                examples.entry(dep_in_same_repo).or_default().insert((
+                    format!("{}::{}", ws_member.name, bin_name),
+                    verification.1.clone(),
+                ));
             }
         }
     }
@@ -202,20 +735,86 @@ pub fn verify_for_gh_pages(
         verifications.entry(ws_member).or_default();
     }
 
+    let mut checkpoints = Checkpoints::load()?;
+    let timestamps_path = timestamps_path.map(|p| cwd.join(p));
+    let mut timestamps = match &timestamps_path {
+        Some(path) => Some(Timestamps::load(path)?),
+        None => None,
+    };
+    let mut tests = vec![];
     for (ws_member, metadata) in &metadata_list {
         let ws_member = &metadata[ws_member];
-        for bin_name in bin_metadata[&ws_member.id].keys() {
-            process_builder::process(&cargo_exes[&metadata.workspace_root])
-                .arg("compete")
-                .arg("t")
-                .arg("--manifest-path")
-                .arg(&ws_member.manifest_path)
-                .arg(bin_name)
-                .cwd(&metadata.workspace_root)
-                .exec_with_status(shell)?;
+        if let Some(reason) = deselection_reason(&ws_member.id) {
+            skipped.insert(ws_member, reason);
+            continue;
+        }
+        if watch || no_test {
+            continue;
+        }
+        for (bin_name, problem) in &bin_metadata[&ws_member.id] {
+            let name = format!("{}::{}", ws_member.name, bin_name);
+            let source_hash = source_hashes[&(&ws_member.id, bin_name)].clone();
+            if let (Some(source_hash), false) = (&source_hash, force) {
+                // The timestamps take the place of the checkpoints so that `max_age` is respected.
+                if let Some(timestamps) = &timestamps {
+                    let manifest_path =
+                        timestamp_manifest_path(repo_workdir, &ws_member.manifest_path);
+                    if timestamps.is_recent(
+                        (&manifest_path, bin_name, problem.as_str()),
+                        source_hash,
+                        last_verified,
+                        max_age,
+                    ) {
+                        shell.status("Cached", &name)?;
+                        continue;
+                    }
+                } else if checkpoints.is_fresh(
+                    ws_member.manifest_path.as_str(),
+                    bin_name,
+                    source_hash,
+                ) {
+                    shell.status("Fresh", &name)?;
+                    continue;
+                }
+            }
+            let command = if let Some(test_command) = &test_commands[&metadata.workspace_root] {
+                let args = test_command
+                    .iter()
+                    .map(|arg| {
+                        arg.replace("{manifest_path}", ws_member.manifest_path.as_str())
+                            .replace("{bin}", bin_name)
+                    })
+                    .collect::<Vec<_>>();
+                process_builder::process(&args[0]).args(&args[1..])
+            } else {
+                process_builder::process(&cargo_exes[&metadata.workspace_root])
+                    .arg("compete")
+                    .arg("t")
+                    .arg("--manifest-path")
+                    .arg(&ws_member.manifest_path)
+                    .arg(bin_name)
+            };
+            let command = command
+                .envs(if offline || frozen {
+                    Some(("CARGO_NET_OFFLINE", "true"))
+                } else {
+                    None
+                })
+                .cwd(&metadata.workspace_root);
+            let command = match test_timeout {
+                Some(test_timeout) => command.timeout(test_timeout),
+                None => command,
+            };
+            tests.push(Test {
+                name,
+                command,
+                manifest_path: &ws_member.manifest_path,
+                bin_name,
+                problem,
+                source_hash,
+            });
         }
     }
-
     let crate_names = metadata_list
         .values()
         .flat_map(|metadata| {
@@ -223,82 +822,415 @@ pub fn verify_for_gh_pages(
                 .workspace_members
                 .iter()
                 .map(move |id| &metadata[id])
+                // Hidden packages have no docs to link to.
+                .filter(|package| !matches!(package.metadata(), Ok(m) if m.cargo_cpl.hidden))
                 .flat_map(|package| {
                     let krate = package
                         .lib_target()
                         .or_else(|| package.proc_macro_target())?;
-                    Some((&package.name, krate.crate_name()))
+                    Some((&*package.name, krate.crate_name()))
                 })
         })
         .collect::<HashMap<_, _>>();
 
+    // `None` if `rustfmt` is unavailable, in which case the "formatted" code sizes are measured
+    // without formatting.
+    let rustfmt_toolchain = if dry_run
+        || matches!(
+            process_builder::toolchain_rustfmt(nightly_toolchain)
+                .arg("--version")
+                .cwd(repo_workdir)
+                .status_silent(shell),
+            Ok(status) if status.success()
+        ) {
+        Some(nightly_toolchain)
+    } else {
+        shell.warn(format!(
+            "`rustfmt` of `{}` is not available. the formatted code sizes are measured without \
+             formatting",
+            nightly_toolchain,
+        ))?;
+        None
+    };
+
+    let analyze = |skipped: &mut Skipped| -> anyhow::Result<_> {
+        let mut analysis = vec![];
+        for (package_id, verifications) in &verifications {
+            let metadata = &metadata_list[*package_id];
+            let package = &metadata[package_id];
+            let krate =
+                if let Some(krate) = package.lib_target().or_else(|| package.proc_macro_target()) {
+                    krate
+                } else {
+                    skipped.insert(package, "no `lib` or `proc-macro` target");
+                    continue;
+                };
+            if package.metadata()?.cargo_cpl.hidden {
+                skipped.insert(package, "`package.metadata.cargo-cpl.hidden` is `true`");
+                continue;
+            }
+            let relative_manifest_path = package
+                .manifest_path
+                .strip_prefix(repo_workdir)
+                .map_err(|_| anyhow!("`{}` is outside of the repository", package.manifest_path))?;
+            let manifest_dir_blob_url =
+                gh_blob_url(&relative_manifest_path.with_file_name(""), None);
+            let crate_name = |k: &str| crate_names.get(k).map(|v| &**v);
+            let dependency_ul = |kind| {
+                package.dependency_ul(kind, &metadata.workspace_root, crate_name, docs_base_url)
+            };
+            let (dev_dependency_ul, build_dependency_ul) = if show_dev_deps {
+                (
+                    Some(dependency_ul(cm::DependencyKind::Development)?),
+                    Some(dependency_ul(cm::DependencyKind::Build)?),
+                )
+            } else {
+                (None, None)
+            };
+            let dependency_ul = dependency_ul(cm::DependencyKind::Normal)?;
+            let code_sizes = krate.is_lib().then(|| {
+                let features = workspace::resolved_features(metadata, &package.id);
+                CodeSizes::new(package, features, krate, rustfmt_toolchain)
+            });
+            if let Some(CodeSizes {
+                unmodified: Err(err),
+                ..
+            }) = &code_sizes
+            {
+                skipped.insert(package, format!("could not measure the code size: {}", err));
+            }
+            analysis.push(PackageAnalysis {
+                package,
+                krate,
+                metadata,
+                workspace_root: &metadata.workspace_root,
+                git_url: gh_url,
+                relative_manifest_path,
+                manifest_dir_blob_url,
+                dependency_ul,
+                dev_dependency_ul,
+                build_dependency_ul,
+                code_sizes,
+                verifications,
+                examples: show_examples.then(|| {
+                    examples
+                        .get(package_id)
+                        .into_iter()
+                        .flatten()
+                        .cloned()
+                        .collect()
+                }),
+                is_selected: is_selected(&package.id),
+                last_verified,
+                freshly_tested: !(watch || no_test),
+            });
+        }
+        Ok(analysis)
+    };
+
+    if dry_run {
+        let workspace_roots = metadata_list
+            .values()
+            .map(|m| &m.workspace_root)
+            .unique()
+            .collect::<Vec<_>>();
+        for workspace_root in workspace_roots {
+            shell.status("Workspace", workspace_root)?;
+        }
+        for Test { name, problem, .. } in &tests {
+            shell.status("Would test", format!("{} ({})", name, problem))?;
+        }
+        for analysis in analyze(&mut skipped)?.iter().filter(|a| a.is_selected) {
+            shell.status("Would doc", &analysis.package.name)?;
+        }
+        shell.status(
+            "Would build",
+            format!(
+                "the docs in `{}`",
+                doc_ws.join("target").join("doc").display()
+            ),
+        )?;
+        shell.status("Cache", cache::cache_dir()?.display())?;
+        skipped.print(shell)?;
+        return Ok(());
+    }
+
+    let started = Instant::now();
+    let results = run_tests(&tests, jobs, keep_going, retries, shell)?;
+    timings.add("test", started);
+    let mut failures = vec![];
+    for (test, passed) in tests.into_iter().zip_eq(results) {
+        if let Some(passed) = passed {
+            if let Some(source_hash) = test.source_hash {
+                checkpoints.insert(
+                    test.manifest_path.as_str(),
+                    test.bin_name,
+                    source_hash.clone(),
+                    passed,
+                );
+                if let Some(timestamps) = &mut timestamps {
+                    let manifest_path = timestamp_manifest_path(repo_workdir, test.manifest_path);
+                    let key = (&*manifest_path, test.bin_name, test.problem.as_str());
+                    if passed {
+                        timestamps.insert(key, source_hash, last_verified);
+                    } else {
+                        timestamps.remove(key);
+                    }
+                }
+            }
+            if !passed {
+                failures.push(test.name);
+            }
+        }
+    }
+    checkpoints.save()?;
+    if let (Some(timestamps), Some(path)) = (&timestamps, &timestamps_path) {
+        timestamps.save(path)?;
+    }
+    if !failures.is_empty() {
+        bail!(
+            "failed to verify {}",
+            failures
+                .iter()
+                .format_with(", ", |s, f| f(&format_args!("`{}`", s))),
+        );
+    }
+
+    let doc_ws = &if out_dir.is_some() {
+        xshell::mkdir_p(doc_ws)?;
+        dunce::canonicalize(doc_ws)
+            .with_context(|| format!("could not canonicalize `{}`", doc_ws.display()))?
+    } else {
+        doc_ws.clone()
+    };
+
+    let analysis = analyze(&mut skipped)?;
+    let open = if let Some(open_crate) = open_crate {
+        let analysis = analysis
+            .iter()
+            .find(|a| a.krate.crate_name() == open_crate || a.package.name == open_crate)
+            .with_context(|| format!("no library named `{}`", open_crate))?;
+        Some(&*analysis.package.name)
+    } else if open {
+        Some("__cargo_cpl_doc")
+    } else {
+        None
+    };
     prepare_doc(
         open,
-        nightly_toolchain,
         repo_workdir,
-        &verifications
-            .iter()
-            .flat_map(|(package_id, verifications)| {
-                let package = &metadata_list[*package_id][package_id];
-                let krate = package
-                    .lib_target()
-                    .or_else(|| package.proc_macro_target())?;
-                Some((package, krate, verifications))
-            })
-            .map(|(package, krate, verifications)| {
-                let relative_manifest_path = package
-                    .manifest_path
-                    .strip_prefix(repo_workdir)
-                    .map_err(|_| {
-                        anyhow!("`{}` is outside of the repository", package.manifest_path)
-                    })?;
-                let manifest_dir_blob_url = gh_blob_url(&relative_manifest_path.with_file_name(""));
-                let dependency_ul = {
-                    let metadata = &metadata_list[&package.id];
-                    let crate_names = metadata
-                        .workspace_members
-                        .iter()
-                        .map(move |id| &metadata[id])
-                        .flat_map(|package| {
-                            let krate = package
-                                .lib_target()
-                                .or_else(|| package.proc_macro_target())?;
-                            Some((&*package.name, krate.crate_name()))
-                        })
-                        .collect::<HashMap<_, _>>();
-                    package.dependency_ul(|k| crate_names.get(k).map(|v| &**v))?
-                };
-                let code_sizes = krate.is_lib().then(|| CodeSizes::new(krate));
-                Ok(PackageAnalysis {
-                    package,
-                    krate,
-                    git_url: gh_url,
-                    relative_manifest_path,
-                    manifest_dir_blob_url,
-                    dependency_ul,
-                    code_sizes,
-                    verifications,
-                })
-            })
-            .collect::<anyhow::Result<Vec<_>>>()?,
+        doc_ws,
+        doc_opts,
+        &analysis,
+        &mut timings,
         shell,
     )?;
+    if emit_summary {
+        write_summary(doc_ws, &analysis, shell)?;
+    }
+    if emit_cverifier {
+        write_cverifier_result(doc_ws, &solution_files, last_verified, shell)?;
+    }
+    timings.print(shell)?;
+
+    if watch {
+        watch_for_changes(repo_workdir, &[doc_ws], shell, |shell| {
+            let analysis = analyze(&mut skipped)?;
+            prepare_doc(
+                None,
+                repo_workdir,
+                doc_ws,
+                doc_opts,
+                &analysis,
+                &mut Timings::default(),
+                shell,
+            )?;
+            if emit_summary {
+                write_summary(doc_ws, &analysis, shell)?;
+            }
+            Ok(())
+        })?;
+    }
+
+    match format {
+        OutputFormat::Human => skipped.print(shell)?,
+        OutputFormat::Json => {
+            let report = Report {
+                packages: analysis
+                    .iter()
+                    .map(|analysis| ReportPackage {
+                        name: &analysis.package.name,
+                        manifest_path: analysis.package.manifest_path.as_str(),
+                        verified: !analysis.verifications.is_empty(),
+                        verifications: analysis
+                            .verifications
+                            .iter()
+                            .map(|(problem, source)| ReportVerification::new(problem, source))
+                            .collect(),
+                        judges: analysis.judges(),
+                    })
+                    .collect(),
+                skipped: skipped
+                    .0
+                    .iter()
+                    .map(|(package, reason)| ReportSkipped { package, reason })
+                    .collect(),
+            };
+            writeln!(shell.out(), "{}", serde_json::to_string(&report)?)?;
+        }
+    }
+
+    for (package_name, name_in_toml) in unused_path_deps {
+        shell.warn(format!(
+            "`{}`: the path dependency `{}` is unused",
+            package_name, name_in_toml,
+        ))?;
+    }
 
+    if deny_unverified {
+        let unverified = analysis
+            .iter()
+            .filter(|a| a.is_selected && a.verifications.is_empty())
+            .map(|a| a.krate.crate_name())
+            .collect::<Vec<_>>();
+        if !unverified.is_empty() {
+            bail!(
+                "unverified libraries: {}",
+                unverified
+                    .iter()
+                    .format_with(", ", |s, f| f(&format_args!("`{}`", s))),
+            );
+        }
+    }
     Ok(())
 }
 
+/// Output of `--format json`.
+#[derive(Serialize)]
+struct Report<'a> {
+    packages: Vec<ReportPackage<'a>>,
+    skipped: Vec<ReportSkipped<'a>>,
+}
+
+#[derive(Serialize)]
+struct ReportPackage<'a> {
+    name: &'a str,
+    manifest_path: &'a str,
+    verified: bool,
+    verifications: Vec<ReportVerification<'a>>,
+    /// Names of the judges of the problems, sorted.
+    judges: Vec<&'static str>,
+}
+
+#[derive(Serialize)]
+struct ReportVerification<'a> {
+    problem: &'a Url,
+    source: &'a Url,
+    /// Name of the judge of `problem`, e.g. `AtCoder`.
+    judge: &'static str,
+}
+
+impl<'a> ReportVerification<'a> {
+    fn new(problem: &'a Url, source: &'a Url) -> Self {
+        Self {
+            problem,
+            source,
+            judge: judge_name(problem),
+        }
+    }
+}
+
+/// Returns the name of the judge hosting the problem, or `other` for unknown hosts.
+fn judge_name(problem: &Url) -> &'static str {
+    match problem.host_str() {
+        Some("atcoder.jp") => "AtCoder",
+        Some("codeforces.com") | Some("m1.codeforces.com") | Some("m2.codeforces.com") => {
+            "Codeforces"
+        }
+        Some("judge.yosupo.jp") => "Library Checker",
+        Some("onlinejudge.u-aizu.ac.jp") | Some("judge.u-aizu.ac.jp") => "AOJ",
+        Some("yukicoder.me") => "yukicoder",
+        Some("www.codechef.com") | Some("codechef.com") => "CodeChef",
+        Some("www.spoj.com") => "SPOJ",
+        _ => "other",
+    }
+}
+
+#[derive(Serialize)]
+struct ReportSkipped<'a> {
+    package: &'a str,
+    reason: &'a str,
+}
+
+/// Content of `verification-summary.json`, written to the doc directory with `--emit-summary`.
+///
+/// Fields are only added to this, not removed or renamed.
+#[derive(Serialize)]
+struct Summary<'a> {
+    /// Every `lib` or `proc-macro` package in the repository, including the deselected ones.
+    libraries: Vec<SummaryLibrary<'a>>,
+}
+
+#[derive(Serialize)]
+struct SummaryLibrary<'a> {
+    /// Package name.
+    name: &'a str,
+    /// Absolute path to the manifest.
+    manifest_path: &'a str,
+    /// Name of the `lib` or `proc-macro` target, with `-` replaced with `_`.
+    crate_name: String,
+    /// Whether `verifications` is not empty.
+    verified: bool,
+    /// Pairs of a problem URL and a blob URL of the solution, with the name of the judge.
+    verifications: Vec<ReportVerification<'a>>,
+    /// Names of the judges of the problems, sorted.
+    judges: Vec<&'static str>,
+    /// `null` for `proc-macro` crates.
+    code_size: Option<SummaryCodeSize>,
+}
+
+/// Sizes of the `lib` target with the `mod`s expanded, in bytes. `null` if they could not be
+/// measured.
+#[derive(Serialize)]
+struct SummaryCodeSize {
+    unmodified: Option<usize>,
+    comments_stripped: Option<usize>,
+    minified: Option<usize>,
+    /// `null` if `rustfmt` is unavailable.
+    formatted: Option<usize>,
+}
+
 struct PackageAnalysis<'a> {
     package: &'a cm::Package,
     krate: &'a cm::Target,
+    /// Metadata of the workspace `package` belongs to.
+    metadata: &'a cm::Metadata,
+    workspace_root: &'a Utf8Path,
     git_url: &'a Url,
     relative_manifest_path: &'a Utf8Path,
     manifest_dir_blob_url: Url,
-    dependency_ul: Vec<(String, String)>,
+    dependency_ul: Vec<(String, String, Option<String>)>,
+    dev_dependency_ul: Option<Vec<(String, String, Option<String>)>>,
+    build_dependency_ul: Option<Vec<(String, String, Option<String>)>>,
     code_sizes: Option<CodeSizes>,
     verifications: &'a BTreeSet<(&'a Url, Url)>,
+    examples: Option<Vec<(String, Url)>>,
+    is_selected: bool,
+    /// Time `verify_for_gh_pages` started at, since the Unix epoch.
+    last_verified: Duration,
+    freshly_tested: bool,
 }
 
 impl PackageAnalysis<'_> {
+    fn judges(&self) -> Vec<&'static str> {
+        self.verifications
+            .iter()
+            .map(|(problem, _)| judge_name(problem))
+            .unique()
+            .sorted()
+            .collect()
+    }
+
     fn to_html_header(&self) -> String {
         format!(
             indoc! {r##"
@@ -312,6 +1244,14 @@ impl PackageAnalysis<'_> {
                     [{}],
                     {},
                     [{}],
+                    {},
+                    {},
+                    {},
+                    {},
+                    {},
+                    {},
+                    {},
+                    {},
                 );
 
                 {}</script>
@@ -324,13 +1264,23 @@ impl PackageAnalysis<'_> {
             )),
             self.dependency_ul
                 .iter()
-                .map(|(s, u)| json!([s, u]))
+                .map(|(s, u, f)| json!([s, u, f]))
                 .join(","),
             json!(self.code_sizes.as_ref().map(CodeSizes::unmodified)),
             self.verifications
                 .iter()
-                .map(|(u1, u2)| json!([u1, u2]))
+                .map(|(u1, u2)| json!([u1, u2, judge_name(u1)]))
                 .join(","),
+            json!(self.examples),
+            json!(self.code_sizes.as_ref().map(CodeSizes::comments_stripped)),
+            json!(self.dev_dependency_ul),
+            json!(self.build_dependency_ul),
+            json!(
+                self.last_verified.as_secs() * 1000 + u64::from(self.last_verified.subsec_millis())
+            ),
+            json!(self.freshly_tested),
+            json!(self.code_sizes.as_ref().map(CodeSizes::formatted)),
+            json!(self.code_sizes.as_ref().map(CodeSizes::minified)),
             include_str!("../injection/dist/index.js").trim_start_matches("\"use strict\";\n"),
         )
     }
@@ -338,41 +1288,157 @@ impl PackageAnalysis<'_> {
 
 struct CodeSizes {
     unmodified: Result<usize, String>,
+    comments_stripped: Result<usize, String>,
+    /// Size with the comments and the redundant whitespace removed.
+    minified: Result<usize, String>,
+    /// Size after `rustfmt`, or the unmodified size if `rustfmt` is unavailable.
+    formatted: Result<usize, String>,
+    /// Whether `formatted` is measured with `rustfmt`.
+    rustfmt_applied: bool,
 }
 
 impl CodeSizes {
-    fn new(krate: &cm::Target) -> Self {
-        match crate::rust::expand_mods(&krate.src_path) {
+    fn new(
+        package: &cm::Package,
+        features: &[String],
+        krate: &cm::Target,
+        rustfmt_toolchain: Option<&str>,
+    ) -> Self {
+        match crate::rust::expand_mods(&krate.src_path, Some(features), 4) {
             Ok(code) => Self {
                 unmodified: Ok(code.len()),
+                comments_stripped: Ok(crate::rust::strip_comments(&code).len()),
+                minified: crate::rust::minify(&code).map(|code| code.len()),
+                formatted: match rustfmt_toolchain {
+                    Some(toolchain) => process_builder::toolchain_rustfmt(toolchain)
+                        .args(&["--edition", &krate.edition])
+                        // For `rustfmt.toml` of the package.
+                        .cwd(package.manifest_path.with_file_name(""))
+                        .read_with_stdin(&code)
+                        .map(|code| code.len())
+                        .map_err(|e| format!("{:#}", e)),
+                    None => Ok(code.len()),
+                },
+                rustfmt_applied: rustfmt_toolchain.is_some(),
             },
             Err(err) => Self {
-                unmodified: Err(err),
+                unmodified: Err(err.clone()),
+                comments_stripped: Err(err.clone()),
+                minified: Err(err.clone()),
+                formatted: Err(err),
+                rustfmt_applied: rustfmt_toolchain.is_some(),
             },
         }
     }
 
+    /// `[size or error, whether rustfmt is applied]`.
+    fn formatted(&self) -> serde_json::Value {
+        match &self.formatted {
+            Ok(n) => json!([n, self.rustfmt_applied]),
+            Err(e) => json!([e, self.rustfmt_applied]),
+        }
+    }
+
     fn unmodified(&self) -> serde_json::Value {
         match &self.unmodified {
             Ok(n) => json!(n),
             Err(e) => json!(e),
         }
     }
+
+    fn comments_stripped(&self) -> serde_json::Value {
+        match &self.comments_stripped {
+            Ok(n) => json!(n),
+            Err(e) => json!(e),
+        }
+    }
+
+    fn minified(&self) -> serde_json::Value {
+        match &self.minified {
+            Ok(n) => json!(n),
+            Err(e) => json!(e),
+        }
+    }
+}
+
+/// Whether `to` is a copy of `from` made by a previous run, judging from the sizes and the
+/// modification times.
+fn is_up_to_date(from: &Path, to: &Path) -> bool {
+    match (fs::metadata(from), fs::metadata(to)) {
+        (Ok(from), Ok(to)) => {
+            from.len() == to.len()
+                && matches!((from.modified(), to.modified()), (Ok(from), Ok(to)) if from <= to)
+        }
+        _ => false,
+    }
 }
 
 trait PackageExt {
     fn dependency_ul<'a>(
         &self,
+        kind: cm::DependencyKind,
+        workspace_root: &Utf8Path,
         crate_name: impl FnMut(&str) -> Option<&'a str>,
-    ) -> anyhow::Result<Vec<(String, String)>>;
+        docs_base_url: &Url,
+    ) -> anyhow::Result<Vec<(String, String, Option<String>)>>;
 }
 
 impl PackageExt for cm::Package {
     fn dependency_ul<'a>(
         &self,
+        kind: cm::DependencyKind,
+        workspace_root: &Utf8Path,
         mut crate_name: impl FnMut(&str) -> Option<&'a str>,
-    ) -> anyhow::Result<Vec<(String, String)>> {
-        let Manifest { dependencies } = toml::from_str(&xshell::read_file(&self.manifest_path)?)?;
+        docs_base_url: &Url,
+    ) -> anyhow::Result<Vec<(String, String, Option<String>)>> {
+        let Manifest {
+            dependencies,
+            dev_dependencies,
+            build_dependencies,
+            ..
+        } = toml::from_str(&xshell::read_file(&self.manifest_path)?)?;
+        let mut dependencies = match kind {
+            cm::DependencyKind::Development => dev_dependencies,
+            cm::DependencyKind::Build => build_dependencies,
+            _ => dependencies,
+        };
+
+        // Replaces `{ workspace = true }` with `[workspace.dependencies]` in the root manifest.
+        if dependencies.values().any(|d| {
+            matches!(
+                d,
+                ManifestDependency::Braced {
+                    workspace: true,
+                    ..
+                }
+            )
+        }) {
+            let root_manifest_path = workspace_root.join("Cargo.toml");
+            let Manifest { workspace, .. } =
+                toml::from_str(&xshell::read_file(&root_manifest_path)?)
+                    .with_context(|| format!("could not parse `{}`", root_manifest_path))?;
+            // Paths in the root manifest are relative to the workspace root.
+            let rel_workspace_root = match self.manifest_dir().strip_prefix(workspace_root) {
+                Ok(rel_manifest_dir) => rel_manifest_dir.iter().map(|_| "..").join("/"),
+                Err(_) => workspace_root.to_string(),
+            };
+            for (name_in_toml, value) in &mut dependencies {
+                if let ManifestDependency::Braced {
+                    workspace: true, ..
+                } = value
+                {
+                    if let Some(mut inherited) = workspace.dependencies.get(name_in_toml).cloned() {
+                        if let ManifestDependency::Braced {
+                            path: Some(path), ..
+                        } = &mut inherited
+                        {
+                            *path = Utf8Path::new(&rel_workspace_root).join(&path).into_string();
+                        }
+                        *value = inherited;
+                    }
+                }
+            }
+        }
 
         let paths = dependencies
             .iter()
@@ -403,51 +1469,38 @@ impl PackageExt for cm::Package {
         return Ok(self
             .dependencies
             .iter()
-            .filter(|cm::Dependency { kind, .. }| *kind == cm::DependencyKind::Normal)
-            .map(
-                |cm::Dependency {
-                     name,
-                     source,
-                     req,
-                     rename,
-                     ..
-                 }| {
-                    if source.as_deref()
-                        == Some("registry+https://github.com/rust-lang/crates.io-index")
-                    {
-                        let req = short_reqs
-                            .get(rename.as_ref().unwrap_or(name))
-                            .cloned()
-                            .unwrap_or_else(|| req.to_string());
-                        (
-                            format!("{} {}", name, req),
-                            format!("https://docs.rs/{}/{}", name, req),
-                        )
-                    } else if let Some(url) = source.as_ref().and_then(|s| s.strip_prefix("git+")) {
-                        (format!("{} (git+{})", name, url), url.to_owned())
-                    } else if let Some(source) = &source {
-                        (format!("{} ({})", name, source), "".to_owned())
-                    } else if let (Some(path), Some(crate_name)) =
-                        (paths.get(name), crate_name(name))
-                    {
-                        (
-                            format!("{} (path+{})", name, path),
-                            format!("../{}/index.html", crate_name),
-                        )
-                    } else {
-                        (format!("{} (unknown)", name), "".to_owned())
-                    }
-                },
-            )
+            .filter(|dep| dep.kind == kind)
+            .map(|dep| {
+                let name_in_toml = dep.rename.as_ref().unwrap_or(&dep.name);
+                dep.to_list_item(
+                    short_reqs.get(name_in_toml).map(|s| &**s),
+                    paths.get(&dep.name).map(|s| s.as_str()),
+                    crate_name(&dep.name),
+                    docs_base_url,
+                )
+            })
             .collect());
 
         #[derive(Deserialize)]
+        #[serde(rename_all = "kebab-case")]
         struct Manifest {
             #[serde(default)]
             dependencies: HashMap<String, ManifestDependency>,
+            #[serde(default)]
+            dev_dependencies: HashMap<String, ManifestDependency>,
+            #[serde(default)]
+            build_dependencies: HashMap<String, ManifestDependency>,
+            #[serde(default)]
+            workspace: ManifestWorkspace,
         }
 
-        #[derive(Deserialize)]
+        #[derive(Deserialize, Default)]
+        struct ManifestWorkspace {
+            #[serde(default)]
+            dependencies: HashMap<String, ManifestDependency>,
+        }
+
+        #[derive(Deserialize, Clone)]
         #[serde(untagged)]
         enum ManifestDependency {
             Version(String),
@@ -455,45 +1508,123 @@ impl PackageExt for cm::Package {
                 package: Option<String>,
                 path: Option<String>,
                 version: Option<String>,
+                /// `true` if inherited from `[workspace.dependencies]`.
+                #[serde(default)]
+                workspace: bool,
             },
         }
     }
 }
 
 trait DependencyExt {
-    fn to_list_item(&self) -> Option<(String, String)>;
+    /// Returns the text, the link, and the features for the dependency list.
+    ///
+    /// `short_req` replaces the version requirement, and `path` and `crate_name` are used for
+    /// path dependencies. crates.io crates are linked to `docs_base_url`.
+    fn to_list_item(
+        &self,
+        short_req: Option<&str>,
+        path: Option<&str>,
+        crate_name: Option<&str>,
+        docs_base_url: &Url,
+    ) -> (String, String, Option<String>);
+
+    /// Returns e.g. `features: derive` or `default-features = false`, or `None` if nothing is
+    /// specified.
+    fn features_note(&self) -> Option<String>;
 }
 
 impl DependencyExt for cm::Dependency {
-    fn to_list_item(&self) -> Option<(String, String)> {
-        (self.kind == cm::DependencyKind::Normal).then(|| ())?;
-        Some(
-            if self.source.as_deref()
-                == Some("registry+https://github.com/rust-lang/crates.io-index")
-            {
-                (
-                    format!("{} {}", self.name, self.req),
-                    format!("https://docs.rs/{}/{}", self.name, self.req),
-                )
-            } else if let Some(url) = self.source.as_ref().and_then(|s| s.strip_prefix("git+")) {
-                (format!("{} (git+{})", self.name, url), url.to_owned())
-            } else if let Some(source) = &self.source {
-                (format!("{} ({})", self.name, source), "".to_owned())
-            } else {
-                //(self.name.clone(), format!("../{}/index.html", self.name))
-                todo!();
-            },
-        )
+    fn to_list_item(
+        &self,
+        short_req: Option<&str>,
+        path: Option<&str>,
+        crate_name: Option<&str>,
+        docs_base_url: &Url,
+    ) -> (String, String, Option<String>) {
+        let (text, href) = if self.source.as_deref()
+            == Some("registry+https://github.com/rust-lang/crates.io-index")
+        {
+            let req = short_req
+                .map(ToOwned::to_owned)
+                .unwrap_or_else(|| self.req.to_string());
+            (
+                format!("{} {}", self.name, req),
+                format!("{}{}/{}", docs_base_url, self.name, req),
+            )
+        } else if let Some(url) = self.source.as_ref().and_then(|s| s.strip_prefix("git+")) {
+            (format!("{} (git+{})", self.name, url), url.to_owned())
+        } else if let Some(source) = &self.source {
+            (format!("{} ({})", self.name, source), "".to_owned())
+        } else if let Some(path) = path {
+            (
+                format!("{} (path+{})", self.name, path),
+                crate_name
+                    .map(|crate_name| format!("../{}/index.html", crate_name))
+                    .unwrap_or_default(),
+            )
+        } else {
+            (format!("{} (unknown)", self.name), "".to_owned())
+        };
+        (text, href, self.features_note())
+    }
+
+    fn features_note(&self) -> Option<String> {
+        let mut notes = vec![];
+        if !self.uses_default_features {
+            notes.push("default-features = false".to_owned());
+        }
+        if !self.features.is_empty() {
+            notes.push(format!("features: {}", self.features.join(", ")));
+        }
+        (!notes.is_empty()).then(|| notes.join(", "))
     }
 }
 
+/// Options for [`prepare_doc`] that do not change between the rebuilds.
+struct DocOptions<'a> {
+    nightly_toolchain: &'a str,
+    cargo_flags: &'a [&'a str],
+    /// Base URL of the docs of the crates.io crates.
+    docs_base_url: &'a Url,
+    offline_assets: bool,
+    toc_sort: TocSort,
+    toc_depth: Option<usize>,
+    copy_exclude: &'a [String],
+    copy_whole_repo: bool,
+    copy_mode: CopyMode,
+    /// `--jobs` of `cargo doc`.
+    jobs: Option<usize>,
+}
+
+/// Builds the docs, opening the docs of the package `open` if any.
 fn prepare_doc(
-    open: bool,
-    nightly_toolchain: &str,
+    open: Option<&str>,
     repo_workdir: &Path,
+    ws: &Path,
+    opts: &DocOptions<'_>,
     analysis: &[PackageAnalysis<'_>],
+    timings: &mut Timings,
     shell: &mut Shell,
 ) -> anyhow::Result<()> {
+    let DocOptions {
+        nightly_toolchain,
+        cargo_flags,
+        docs_base_url,
+        offline_assets,
+        toc_sort,
+        toc_depth,
+        copy_exclude,
+        copy_whole_repo,
+        copy_mode,
+        jobs,
+    } = *opts;
+
+    // Held until the docs are built.
+    let _lock = cache::lock_dir(ws, shell)?;
+
+    let started = Instant::now();
+
     let manifest = &mut indoc! {r#"
         [workspace]
         members = []
@@ -505,11 +1636,23 @@ fn prepare_doc(
 
         [lib]
         name = "__TOC"
+
+        [dependencies]
     "#}
     .parse::<toml_edit::Document>()
     .unwrap();
 
+    // The crates are linked with intra-doc links so that `cargo doc` checks them, unless their
+    // crate names are ambiguous.
+    let crate_name_counts = analysis
+        .iter()
+        .map(|PackageAnalysis { krate, .. }| krate.crate_name())
+        .counts();
+    let is_linkable = |crate_name: &str| crate_name_counts[crate_name] == 1;
+
     for PackageAnalysis {
+        package,
+        krate,
         relative_manifest_path,
         ..
     } in analysis
@@ -524,26 +1667,85 @@ fn prepare_doc(
             .unwrap()
             .push(dst.as_str())
             .unwrap();
+
+        let crate_name = &krate.crate_name();
+        if is_linkable(crate_name) {
+            let mut dep = toml_edit::InlineTable::default();
+            dep.get_or_insert("package", &*package.name);
+            dep.get_or_insert("path", dst.as_str());
+            dep.fmt();
+            manifest["dependencies"][crate_name] = toml_edit::value(dep);
+        }
+    }
+
+    // The members may inherit `[workspace.dependencies]` of their original workspaces.
+    for workspace_root in analysis.iter().map(|a| a.workspace_root).unique() {
+        let root_manifest_path = workspace_root.join("Cargo.toml");
+        let root_manifest = xshell::read_file(&root_manifest_path)?
+            .parse::<toml_edit::Document>()
+            .with_context(|| format!("could not parse `{}`", root_manifest_path))?;
+        let dependencies = match root_manifest["workspace"]["dependencies"].as_table_like() {
+            Some(dependencies) => dependencies,
+            None => continue,
+        };
+        let rel_workspace_root = workspace_root
+            .strip_prefix(repo_workdir)
+            .map_err(|_| anyhow!("`{}` is outside of the repository", workspace_root))?;
+        if manifest["workspace"]["dependencies"].is_none() {
+            manifest["workspace"]["dependencies"] = toml_edit::table();
+        }
+        for (name, dependency) in dependencies.iter() {
+            if !manifest["workspace"]["dependencies"][name].is_none() {
+                continue;
+            }
+            let mut dependency = dependency.clone();
+            if let Some(path) = dependency["path"].as_str() {
+                let path = Utf8Path::new(".")
+                    .join("copy")
+                    .join(rel_workspace_root)
+                    .join(path);
+                dependency["path"] = toml_edit::value(path.as_str());
+            }
+            manifest["workspace"]["dependencies"][name] = dependency;
+        }
     }
 
     let toc = &mut TableOfContents::default();
     for PackageAnalysis {
+        package,
         krate,
         relative_manifest_path,
         verifications,
         ..
     } in analysis
     {
+        let crate_name = &krate.crate_name();
+        let link = if is_linkable(crate_name) {
+            crate_name.clone()
+        } else {
+            format!("../{}/index.html", crate_name)
+        };
+        let PackageMetadataCargoCpl {
+            display_name,
+            category,
+            ..
+        } = package.metadata()?.cargo_cpl;
+        let folders = match &category {
+            Some(category) => category.split('/').filter(|s| !s.is_empty()).collect(),
+            None => TableOfContents::folders(relative_manifest_path),
+        };
         toc.insert(
-            relative_manifest_path,
-            &krate.crate_name(),
+            &folders,
+            display_name.as_deref().unwrap_or(crate_name),
+            &link,
             !verifications.is_empty(),
+            toc_depth,
         );
     }
 
     let mut lib_rs = "//! # Table of contents\n".to_owned();
     lib_rs += "//!\n";
-    for line in toc.to_md().lines() {
+    for line in toc.to_md(offline_assets, toc_sort).lines() {
         lib_rs += "//!";
         if !line.is_empty() {
             lib_rs += " ";
@@ -560,92 +1762,688 @@ fn prepare_doc(
     }
     lib_rs += "//! ```\n";
 
-    let ws = &dirs_next::cache_dir()
-        .with_context(|| "could not find the cache directory")?
-        .join("cargo-cpl")
-        .join("workspace");
-
     xshell::mkdir_p(ws.join(".cargo"))?;
     xshell::mkdir_p(ws.join("src"))?;
-    xshell::rm_rf(ws.join("copy"))?;
-    xshell::rm_rf(ws.join("target").join("doc"))?;
+    if analysis.iter().all(|a| a.is_selected) {
+        xshell::rm_rf(ws.join("target").join("doc"))?;
+    }
 
-    xshell::write_file(ws.join(".cargo").join("config.toml"), CONFIG_TOML)?;
+    xshell::write_file(
+        ws.join(".cargo").join("config.toml"),
+        format!(
+            "[doc.extern-map.registries]\ncrates-io = {}\n",
+            toml::Value::from(docs_base_url.as_str()),
+        ),
+    )?;
     xshell::write_file(ws.join("Cargo.toml"), manifest.to_string())?;
     xshell::write_file(ws.join("src").join("lib.rs"), lib_rs)?;
 
-    for result in Walk::new(repo_workdir) {
-        let from = &result?.into_path();
-        if !from.is_file() {
-            continue;
+    // Only the packages to document and their path dependencies, direct or indirect, are needed.
+    // The ones through hidden packages, which are not in `analysis`, are included.
+    let mut package_dirs = BTreeSet::new();
+    for PackageAnalysis {
+        package, metadata, ..
+    } in analysis
+    {
+        let deps = metadata
+            .resolve
+            .as_ref()
+            .unwrap()
+            .nodes
+            .iter()
+            .map(|cm::Node { id, deps, .. }| (id, deps))
+            .collect::<HashMap<_, _>>();
+        let visited = &mut hashset!();
+        let stack = &mut vec![&package.id];
+        while let Some(package_id) = stack.pop() {
+            let package = &metadata[package_id];
+            // Packages from registries or Git repositories never depend on path ones.
+            if package.source.is_some() || !visited.insert(package_id) {
+                continue;
+            }
+            package_dirs.insert(PathBuf::from(package.manifest_dir()));
+            stack.extend(deps[package_id].iter().map(|cm::NodeDep { pkg, .. }| pkg));
         }
+    }
+    package_dirs.retain(|dir| dir.starts_with(repo_workdir));
+    let copy_roots = if copy_whole_repo {
+        vec![repo_workdir]
+    } else {
+        package_dirs.iter().map(|dir| &**dir).collect()
+    };
+    let files = files_to_copy(repo_workdir, &copy_roots, copy_exclude)?;
+    let copy_dir = &ws.join("copy");
+    let mut num_skipped = 0;
+    let mut copies = BTreeMap::new();
+    for from in &files {
         if from.file_name() == Some("Cargo.toml".as_ref())
-            && !analysis
+            && !package_dirs
                 .iter()
-                .any(|PackageAnalysis { package, .. }| package.manifest_path == *from)
+                .any(|dir| dir.join("Cargo.toml") == *from)
         {
-            shell.status("Skipping", format!("Copying {}", from.display()))?;
+            shell.status_with_verbosity(
+                Verbosity::Verbose,
+                "Skipping",
+                format!("Copying {}", from.display()),
+            )?;
+            num_skipped += 1;
             continue;
         }
         if let Ok(rel_path) = from.strip_prefix(repo_workdir) {
-            if let Some(rel_path) = rel_path.to_str() {
-                let to = &ws.join("copy").join(rel_path);
-                xshell::mkdir_p(to.with_file_name(""))?;
-                xshell::cp(from, to)?;
-                shell.status(
-                    "Copied",
-                    format!("`{}` to `{}`", from.display(), to.display()),
-                )?;
+            if rel_path.to_str().is_some() {
+                copies.insert(copy_dir.join(rel_path), from);
             }
         }
     }
 
-    if process_builder::process("rustup")
-        .args(&["which", "cargo-fmt", "--toolchain", nightly_toolchain])
+    // Remove the files left by the previous runs that are no longer needed, including the
+    // temporary files of an interrupted run.
+    let mut num_removed = 0;
+    if copy_dir.exists() {
+        let stale_files = WalkBuilder::new(copy_dir)
+            .standard_filters(false)
+            .build()
+            .map(|e| e.map(ignore::DirEntry::into_path))
+            .filter(|p| !matches!(p, Ok(p) if !p.is_file() || copies.contains_key(p)))
+            .collect::<Result<Vec<_>, _>>()?;
+        for stale_file in stale_files {
+            xshell::rm_rf(&stale_file)?;
+            shell.status_with_verbosity(
+                Verbosity::Verbose,
+                "Removed",
+                format!("`{}`", stale_file.display()),
+            )?;
+            num_removed += 1;
+        }
+    }
+
+    let (mut num_copied, mut num_fresh) = (0, 0);
+    for (i, (to, from)) in copies.iter().enumerate() {
+        shell.progress("Copying", i, copies.len())?;
+        if is_up_to_date(from, to) {
+            num_fresh += 1;
+            continue;
+        }
+        xshell::mkdir_p(to.with_file_name(""))?;
+        // Copy to a temporary file and rename it so that an interrupted run does not leave a
+        // partially written file.
+        let tmp = &{
+            let mut tmp = to.clone().into_os_string();
+            tmp.push(".cargo-cpl-tmp");
+            PathBuf::from(tmp)
+        };
+        xshell::rm_rf(tmp)?;
+        let linked = copy_mode == CopyMode::Hardlink && fs::hard_link(from, tmp).is_ok();
+        if !linked {
+            xshell::cp(from, tmp)?;
+        }
+        fs::rename(tmp, to).with_context(|| format!("could not rename `{}`", tmp.display()))?;
+        shell.status_with_verbosity(
+            Verbosity::Verbose,
+            "Copied",
+            format!("`{}` to `{}`", from.display(), to.display()),
+        )?;
+        num_copied += 1;
+    }
+    shell.clear_progress()?;
+    shell.status(
+        "Copied",
+        format!(
+            "{} file(s) to `{}` ({} up to date, {} removed, {} manifest(s) skipped)",
+            num_copied,
+            copy_dir.display(),
+            num_fresh,
+            num_removed,
+            num_skipped,
+        ),
+    )?;
+    timings.add("copy", started);
+
+    let started = Instant::now();
+    if process_builder::toolchain_cargo(nightly_toolchain)
+        .args(&["fmt", "--version"])
         .cwd(ws)
-        .status_silent()?
+        .status_silent(shell)?
         .success()
     {
-        process_builder::process("rustup")
-            .args(&["run", nightly_toolchain, "cargo", "fmt"])
+        process_builder::toolchain_cargo(nightly_toolchain)
+            .arg("fmt")
             .cwd(ws)
             .exec_with_status(shell)?;
     }
 
-    let run_cargo_doc = |p: &str, open: bool, rustdocflags: Option<&str>, shell: &mut Shell| -> _ {
-        process_builder::process("rustup")
-            .args(&[
-                "run",
-                nightly_toolchain,
-                "cargo",
-                "doc",
-                "-p",
-                p,
-                "--no-deps",
-                "-Zrustdoc-map",
-            ])
+    let headers_dir = &ws.join("headers");
+    let current_exe = env::current_exe()?;
+    let cargo_doc = |packages: &[&str], open: bool, with_headers: bool| {
+        let mut cargo_doc = process_builder::toolchain_cargo(nightly_toolchain).arg("doc");
+        for package in packages {
+            cargo_doc = cargo_doc.args(&["-p", package]);
+        }
+        // `CARGO_ENCODED_RUSTDOCFLAGS` applies to all of the crates, so this executable is run as
+        // `rustdoc` to give each crate its own header.
+        let rustdoc = with_headers.then(|| {
+            vec![
+                (RUSTDOC_HEADERS_DIR, headers_dir.clone().into_os_string()),
+                (
+                    RUSTDOC_ORIGINAL,
+                    env::var_os("RUSTDOC").unwrap_or_else(|| "rustdoc".into()),
+                ),
+                ("RUSTDOC", current_exe.clone().into_os_string()),
+            ]
+        });
+        cargo_doc
+            .args(&["--no-deps", "-Zrustdoc-map"])
+            .args(
+                &jobs
+                    .map(|jobs| format!("-j{}", jobs))
+                    .into_iter()
+                    .collect::<Vec<_>>(),
+            )
             .args(if open { &["--open"] } else { &[] })
-            .envs(rustdocflags.map(|v| ("RUSTDOCFLAGS", v)))
+            .args(cargo_flags)
+            .envs(rustdoc.into_iter().flatten())
+            // The docs are expected in `target/doc`, not in `target/<triple>/doc`.
+            .env_remove("CARGO_BUILD_TARGET")
             .cwd(ws)
-            .exec_with_status(shell)
     };
 
-    for analysis in analysis {
-        xshell::write_file(ws.join("header.html"), analysis.to_html_header())?;
-        run_cargo_doc(
-            &analysis.package.name,
-            false,
-            Some("--html-in-header ./header.html"),
-            shell,
-        )?;
+    xshell::rm_rf(ws.join("header.html"))?;
+    xshell::rm_rf(headers_dir)?;
+    xshell::mkdir_p(headers_dir)?;
+    let packages = analysis
+        .iter()
+        .filter(|a| a.is_selected || open == Some(&a.package.name))
+        .map(|analysis| {
+            let header = headers_dir.join(format!("{}.html", analysis.krate.crate_name()));
+            xshell::write_file(header, analysis.to_html_header())?;
+            Ok(&*analysis.package.name)
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    // The crates are documented at once, since `cargo doc`s sharing the target directory would
+    // wait for each other.
+    if !packages.is_empty() {
+        cargo_doc(&packages, false, true).exec_with_status(shell)?;
+    }
+    match open {
+        // Only opens the docs, which are already built.
+        Some(open) if packages.contains(&open) => {
+            cargo_doc(&[open], true, true).exec_with_status(shell)?;
+        }
+        _ => {}
+    }
+    cargo_doc(&["__cargo_cpl_doc"], open == Some("__cargo_cpl_doc"), false)
+        .exec_with_status(shell)?;
+    timings.add("doc", started);
+    shell.status("Finished", ws.join("target").join("doc").display())?;
+    Ok(())
+}
+
+/// Lists the files under `roots` in the repository, except the ones in `target` and `.git`, the
+/// ones ignored by Git, and the ones matching `copy_exclude`.
+fn files_to_copy(
+    repo_workdir: &Path,
+    roots: &[&Path],
+    copy_exclude: &[String],
+) -> anyhow::Result<BTreeSet<PathBuf>> {
+    let mut overrides = OverrideBuilder::new(repo_workdir);
+    for glob in ["target/", ".git/"]
+        .iter()
+        .copied()
+        .chain(copy_exclude.iter().map(|s| &**s))
+    {
+        overrides
+            .add(&format!("!{}", glob))
+            .with_context(|| format!("invalid glob: `{}`", glob))?;
+    }
+    let overrides = overrides.build()?;
+    roots
+        .iter()
+        .flat_map(|root| WalkBuilder::new(root).overrides(overrides.clone()).build())
+        .map(|e| e.map(ignore::DirEntry::into_path))
+        .filter(|p| !matches!(p, Ok(p) if !p.is_file()))
+        .collect::<Result<_, _>>()
+        .map_err(Into::into)
+}
+
+/// Environment variable set to the directory of the headers of the crates, for
+/// [`run_as_rustdoc`].
+const RUSTDOC_HEADERS_DIR: &str = "__CARGO_CPL_RUSTDOC_HEADERS_DIR";
+/// Environment variable set to the `rustdoc` to run, for [`run_as_rustdoc`].
+const RUSTDOC_ORIGINAL: &str = "__CARGO_CPL_RUSTDOC";
+
+/// If this executable is run by `cargo doc` as `rustdoc`, runs the actual `rustdoc` with the
+/// header of the crate being documented, and returns its exit code.
+pub fn run_as_rustdoc() -> anyhow::Result<Option<i32>> {
+    let headers_dir = match env::var_os(RUSTDOC_HEADERS_DIR) {
+        Some(headers_dir) => PathBuf::from(headers_dir),
+        None => return Ok(None),
+    };
+    let rustdoc = env::var_os(RUSTDOC_ORIGINAL).unwrap_or_else(|| "rustdoc".into());
+    let args = env::args_os().skip(1).collect::<Vec<_>>();
+    let header = args
+        .iter()
+        .skip_while(|arg| *arg != "--crate-name")
+        .nth(1)
+        .map(|crate_name| headers_dir.join(crate_name).with_extension("html"))
+        .filter(|header| header.exists());
+    let status = process::Command::new(&rustdoc)
+        .args(&args)
+        .args(
+            header
+                .iter()
+                .flat_map(|h| vec!["--html-in-header".as_ref(), h.as_os_str()]),
+        )
+        .status()
+        .with_context(|| format!("could not execute `{}`", rustdoc.to_string_lossy()))?;
+    Ok(Some(status.code().unwrap_or(1)))
+}
+
+/// Wall-clock time spent in each phase of `verify gh-pages`.
+#[derive(Default)]
+struct Timings(Vec<(&'static str, Duration)>);
+
+impl Timings {
+    /// Adds the time since `started` to `phase`.
+    fn add(&mut self, phase: &'static str, started: Instant) {
+        let elapsed = started.elapsed();
+        match self.0.iter_mut().find(|(p, _)| *p == phase) {
+            Some((_, total)) => *total += elapsed,
+            None => self.0.push((phase, elapsed)),
+        }
+    }
+
+    /// Prints the time of each phase with `-v`.
+    fn print(&self, shell: &mut Shell) -> io::Result<()> {
+        shell.status_with_verbosity(
+            Verbosity::Verbose,
+            "Timings",
+            self.0.iter().format_with(", ", |(phase, elapsed), f| {
+                f(&format_args!("{} {:.1}s", phase, elapsed.as_secs_f64()))
+            }),
+        )
+    }
+}
+
+/// Writes [`Summary`] next to the generated docs.
+fn write_summary(
+    ws: &Path,
+    analysis: &[PackageAnalysis<'_>],
+    shell: &mut Shell,
+) -> anyhow::Result<()> {
+    let path = ws
+        .join("target")
+        .join("doc")
+        .join("verification-summary.json");
+    let summary = Summary {
+        libraries: analysis
+            .iter()
+            .map(|analysis| SummaryLibrary {
+                name: &analysis.package.name,
+                manifest_path: analysis.package.manifest_path.as_str(),
+                crate_name: analysis.krate.crate_name(),
+                verified: !analysis.verifications.is_empty(),
+                verifications: analysis
+                    .verifications
+                    .iter()
+                    .map(|(problem, source)| ReportVerification::new(problem, source))
+                    .collect(),
+                judges: analysis.judges(),
+                code_size: analysis
+                    .code_sizes
+                    .as_ref()
+                    .map(|code_sizes| SummaryCodeSize {
+                        unmodified: code_sizes.unmodified.as_ref().ok().copied(),
+                        comments_stripped: code_sizes.comments_stripped.as_ref().ok().copied(),
+                        minified: code_sizes.minified.as_ref().ok().copied(),
+                        formatted: code_sizes
+                            .formatted
+                            .as_ref()
+                            .ok()
+                            .copied()
+                            .filter(|_| code_sizes.rustfmt_applied),
+                    }),
+            })
+            .collect(),
+    };
+    xshell::write_file(&path, serde_json::to_string_pretty(&summary)?)?;
+    shell.status("Wrote", path.display())?;
+    Ok(())
+}
+
+/// Writes the results in the format of `result.json` of
+/// [competitive-verifier](https://github.com/competitive-verifier/competitive-verifier) 2.x
+/// (`VerifyCommandResult`), next to the generated docs.
+///
+/// Each solution file is mapped to one verification per problem. The elapsed times are not
+/// measured and written as `0.0`.
+fn write_cverifier_result(
+    ws: &Path,
+    solution_files: &BTreeMap<String, (BTreeSet<&Url>, bool)>,
+    last_verified: Duration,
+    shell: &mut Shell,
+) -> anyhow::Result<()> {
+    let path = ws
+        .join("target")
+        .join("doc")
+        .join("competitive-verifier-result.json");
+    let total_seconds = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .ok()
+        .and_then(|now| now.checked_sub(last_verified))
+        .unwrap_or_default()
+        .as_secs_f64();
+    let last_execution_time = rfc3339(last_verified);
+    let files = solution_files
+        .iter()
+        .map(|(file, (problems, tested))| {
+            let verifications = problems
+                .iter()
+                .map(|problem| {
+                    json!({
+                        "verification_name": problem,
+                        "status": if *tested { "success" } else { "skipped" },
+                        "elapsed": 0.0,
+                        "last_execution_time": last_execution_time,
+                    })
+                })
+                .collect::<Vec<_>>();
+            (
+                file.clone(),
+                json!({ "verifications": verifications, "newest": true }),
+            )
+        })
+        .collect::<serde_json::Map<_, _>>();
+    let result = json!({ "total_seconds": total_seconds, "files": files });
+    xshell::write_file(&path, serde_json::to_string_pretty(&result)?)?;
+    shell.status("Wrote", path.display())?;
+    Ok(())
+}
+
+/// Formats the time since the Unix epoch like `2021-01-01T00:00:00+00:00`.
+fn rfc3339(since_epoch: Duration) -> String {
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}+00:00",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+    )
+}
+
+fn specified_packages<'a>(
+    specs: &[String],
+    metadata_list: &'a IndexMap<cm::PackageId, Rc<cm::Metadata>>,
+) -> anyhow::Result<HashSet<&'a cm::PackageId>> {
+    specs
+        .iter()
+        .map(|spec| {
+            metadata_list
+                .iter()
+                .find(|(id, metadata)| metadata[id].name == *spec)
+                .map(|(id, _)| id)
+                .with_context(|| format!("package `{}` is not a member of the workspaces", spec))
+        })
+        .collect()
+}
+
+/// Returns the workspace members that own any of `changed_files` or depend on such packages.
+fn affected_packages<'a>(
+    changed_files: &[PathBuf],
+    metadata_list: &'a IndexMap<cm::PackageId, Rc<cm::Metadata>>,
+) -> io::Result<HashSet<&'a cm::PackageId>> {
+    let manifest_dirs = metadata_list
+        .iter()
+        .map(|(id, metadata)| Ok((id, dunce::canonicalize(metadata[id].manifest_dir())?)))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let changed = changed_files
+        .iter()
+        .flat_map(|file| {
+            manifest_dirs
+                .iter()
+                .filter(|(_, dir)| file.starts_with(dir))
+                .max_by_key(|(_, dir)| dir.components().count())
+                .map(|(id, _)| *id)
+        })
+        .collect::<HashSet<_>>();
+
+    Ok(metadata_list
+        .iter()
+        .filter(|(id, metadata)| {
+            let normal_deps = normal_deps(metadata);
+            let mut visited = hashset!();
+            let mut stack = vec![*id];
+            while let Some(id) = stack.pop() {
+                if visited.insert(id) {
+                    stack.extend(normal_deps[id].iter().map(|(_, pkg)| *pkg));
+                }
+            }
+            visited.iter().any(|id| changed.contains(id))
+        })
+        .map(|(id, _)| id)
+        .collect())
+}
+
+/// Packages left out of some of the steps, with the reasons.
+#[derive(Default)]
+struct Skipped(BTreeSet<(String, String)>);
+
+impl Skipped {
+    fn insert(&mut self, package: &cm::Package, reason: impl Into<String>) {
+        self.0.insert((package.name.clone(), reason.into()));
+    }
+
+    fn print(&self, shell: &mut Shell) -> io::Result<()> {
+        for (package_name, reason) in &self.0 {
+            shell.status("Skipped", format!("`{}`: {}", package_name, reason))?;
+        }
+        Ok(())
+    }
+}
+
+struct Test<'a> {
+    name: String,
+    command: ProcessBuilder<Present>,
+    manifest_path: &'a Utf8Path,
+    bin_name: &'a str,
+    problem: &'a Url,
+    source_hash: Option<String>,
+}
+
+/// Runs the tests in parallel, printing the output of each test at once.
+///
+/// Returns whether each test passed, or `None` if it was not run. Unless `keep_going` is `true`,
+/// the tests not yet started are cancelled after the first failure.
+fn run_tests(
+    tests: &[Test<'_>],
+    jobs: Option<usize>,
+    keep_going: bool,
+    retries: u32,
+    shell: &mut Shell,
+) -> anyhow::Result<Vec<Option<bool>>> {
+    let shell = &Mutex::new(shell);
+    let failed = &AtomicBool::new(false);
+
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs.unwrap_or(0))
+        .build()?
+        .install(|| {
+            tests
+                .par_iter()
+                .map(|Test { name, command, .. }| -> anyhow::Result<_> {
+                    if !keep_going && failed.load(atomic::Ordering::SeqCst) {
+                        return Ok(None);
+                    }
+                    shell.lock().unwrap().status("Running", command)?;
+                    let prefix = &format!("[{}]", name);
+                    for attempt in 1.. {
+                        let status = command.stream_with_prefix(prefix, shell);
+
+                        let mut shell = shell.lock().unwrap();
+                        match status {
+                            Ok(status) => {
+                                if status.success() {
+                                    return Ok(Some(true));
+                                }
+                                shell.warn(format!(
+                                    "{} didn't exit successfully: {}",
+                                    command, status,
+                                ))?;
+                            }
+                            Err(err) => shell.warn(err.downcast::<TimedOut>()?)?,
+                        }
+                        if attempt > retries {
+                            break;
+                        }
+                        let backoff = Duration::from_secs(1 << (attempt - 1).min(5));
+                        shell.status(
+                            "Retrying",
+                            format!(
+                                "{} in {}s ({}/{})",
+                                command,
+                                backoff.as_secs(),
+                                attempt,
+                                retries,
+                            ),
+                        )?;
+                        drop(shell);
+                        thread::sleep(backoff);
+                    }
+                    failed.store(true, atomic::Ordering::SeqCst);
+                    Ok(Some(false))
+                })
+                .collect()
+        })
+}
+
+/// Makes `manifest_path` relative to the repository for [`Timestamps`], so that the keys do not
+/// depend on where the repository is cloned.
+fn timestamp_manifest_path(repo_workdir: &Path, manifest_path: &Utf8Path) -> String {
+    let manifest_path = Path::new(manifest_path);
+    manifest_path
+        .strip_prefix(repo_workdir)
+        .unwrap_or(manifest_path)
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+/// Hashes what the result of the test of a `bin` target depends on: the manifests and the expanded
+/// sources of the targets, the test cases, the test command, and the version of the toolchain.
+///
+/// The test cases are taken as the files in `testcases` of `bin_package`, where `cargo compete`
+/// puts them by default, and `compete.toml` of the workspace, which may move them.
+///
+/// Returns `None` if any of them could not be read.
+fn source_hash<'a>(
+    targets: impl IntoIterator<Item = (&'a cm::Package, &'a cm::Target)>,
+    bin_package: &cm::Package,
+    workspace_root: &Utf8Path,
+    test_command: Option<&[String]>,
+    cargo_version: Option<&str>,
+) -> Option<String> {
+    let mut hasher = Sha256::new();
+    for (package, target) in targets {
+        hasher.update(xshell::read_file(&package.manifest_path).ok()?);
+        hasher.update(crate::rust::expand_mods(&target.src_path, None, 4).ok()?);
+    }
+
+    let compete_toml = workspace_root.join("compete.toml");
+    let test_case_dir = bin_package.manifest_dir().join("testcases");
+    let test_case_files = test_case_dir
+        .is_dir()
+        .then(|| {
+            WalkBuilder::new(test_case_dir)
+                .standard_filters(false)
+                .sort_by_file_path(Ord::cmp)
+                .build()
+                .map(|e| e.map(ignore::DirEntry::into_path))
+                .filter(|p| !matches!(p, Ok(p) if !p.is_file()))
+        })
+        .into_iter()
+        .flatten();
+    for path in iter::once(Ok(compete_toml.into()))
+        .chain(test_case_files)
+        .filter(|p| !matches!(p, Ok(p) if !p.exists()))
+    {
+        let path = path.ok()?;
+        hasher.update(path.to_str()?);
+        hasher.update(b"\0");
+        hasher.update(fs::read(path).ok()?);
+    }
+
+    for arg in test_command.into_iter().flatten() {
+        hasher.update(arg);
+        hasher.update(b"\0");
     }
-    run_cargo_doc("__cargo_cpl_doc", open, None, shell)?;
-    return Ok(());
+    hasher.update(cargo_version.unwrap_or_default());
+    Some(format!("{:x}", hasher.finalize()))
+}
+
+/// Calls `rebuild` each time files in `dir` change, until interrupted with Ctrl-C.
+///
+/// The changes in `target`, `.git`, and `ignored` are ignored, so that the files written by
+/// `rebuild` itself do not trigger another rebuild.
+fn watch_for_changes(
+    dir: &Path,
+    ignored: &[&Path],
+    shell: &mut Shell,
+    mut rebuild: impl FnMut(&mut Shell) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let _ctrlc = process_builder::watch_ctrlc()?;
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::watcher(tx, Duration::from_millis(500))?;
+    watcher.watch(dir, RecursiveMode::Recursive)?;
+    shell.status("Watching", dir.display())?;
 
-    static CONFIG_TOML: &str = indoc! {r#"
-        [doc.extern-map.registries]
-        crates-io = "https://docs.rs/"
-    "#};
+    while !process_builder::interrupted() {
+        let event = match rx.recv_timeout(Duration::from_millis(200)) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        let is_relevant = |path: &Path| {
+            !ignored.iter().any(|ignored| path.starts_with(ignored))
+                && !path
+                    .strip_prefix(dir)
+                    .unwrap_or(path)
+                    .iter()
+                    .any(|c| c == "target" || c == ".git")
+        };
+        let changed = match &event {
+            DebouncedEvent::Create(path)
+            | DebouncedEvent::Write(path)
+            | DebouncedEvent::Remove(path) => is_relevant(path),
+            DebouncedEvent::Rename(from, to) => is_relevant(from) || is_relevant(to),
+            DebouncedEvent::Rescan => true,
+            _ => false,
+        };
+        if !changed {
+            continue;
+        }
+        if let Err(err) = rebuild(shell) {
+            shell.error(format!("{:#}", err))?;
+        }
+        shell.status("Watching", dir.display())?;
+    }
+    Ok(())
 }
 
 #[derive(Debug, Deserialize)]
@@ -661,57 +2459,268 @@ struct CargoUdepsOutputDeps {
 
 #[derive(Default)]
 struct TableOfContents {
-    crates: BTreeMap<String, bool>,
+    /// Labels to the link destinations and whether the crates are verified.
+    crates: BTreeMap<String, (String, bool)>,
     children: BTreeMap<String, Self>,
 }
 
 impl TableOfContents {
-    fn insert(&mut self, relative_manifest_path: &Utf8Path, crate_name: &str, is_verified: bool) {
-        let category = &mut relative_manifest_path
+    /// Returns the directories containing the package directory.
+    fn folders(relative_manifest_path: &Utf8Path) -> Vec<&str> {
+        relative_manifest_path
             .parent()
             .unwrap()
             .iter()
             .take(relative_manifest_path.iter().count().saturating_sub(2))
-            .map(ToOwned::to_owned);
+            .collect()
+    }
+
+    /// Inserts a crate labeled `name` under `folders`.
+    ///
+    /// Only the first `max_depth` folders are created, and the rest are prepended to the label.
+    fn insert(
+        &mut self,
+        folders: &[&str],
+        name: &str,
+        link: &str,
+        is_verified: bool,
+        max_depth: Option<usize>,
+    ) {
+        let (folders, rest) = folders.split_at(max_depth.unwrap_or(usize::MAX).min(folders.len()));
 
         let mut entry = self;
-        for category in category {
-            entry = entry.children.entry(category).or_default();
+        for folder in folders {
+            entry = entry.children.entry((*folder).to_owned()).or_default();
         }
-        entry.crates.insert(crate_name.to_owned(), is_verified);
+        let label = rest.iter().chain(&[name]).join("/");
+        entry.crates.insert(label, (link.to_owned(), is_verified));
     }
 
-    fn to_md(&self) -> String {
+    /// Renders this as a Markdown list. With `offline_assets`, the icons are embedded as data URIs.
+    fn to_md(&self, offline_assets: bool, sort: TocSort) -> String {
+        let icon = |url: &str, png: &[u8], alt: &str| {
+            let src = if offline_assets {
+                format!("data:image/png;base64,{}", base64(png))
+            } else {
+                url.to_owned()
+            };
+            format!(
+                r#"<img src="{}" alt="{}" title="{}" width="20" height="20">"#,
+                src, alt, alt,
+            )
+        };
+        let icons = (
+            icon(HEAVY_CHECK_MARK_URL, HEAVY_CHECK_MARK_PNG, "✔"),
+            icon(WARNING_URL, WARNING_PNG, "⚠"),
+        );
+
         let mut ret = "".to_owned();
-        to_md(self, 0, &mut ret);
+        to_md(self, 0, &icons, sort, &mut ret);
         return ret;
 
-        fn to_md(this: &TableOfContents, depth: usize, ret: &mut String) {
-            for (crate_name, is_verified) in &this.crates {
+        fn to_md(
+            this: &TableOfContents,
+            depth: usize,
+            icons: &(String, String),
+            sort: TocSort,
+            ret: &mut String,
+        ) {
+            let (heavy_check_mark, warning) = icons;
+            let mut crates = this.crates.iter().collect::<Vec<_>>();
+            if sort == TocSort::VerifiedFirst {
+                crates.sort_by_key(|&(label, (_, is_verified))| (!is_verified, label));
+            }
+            for (label, (link, is_verified)) in crates {
                 *ret += &" ".repeat(4 * depth);
                 *ret += "- ";
                 *ret += if *is_verified {
-                    HEAVY_CHECK_MARK
+                    heavy_check_mark
                 } else {
-                    WARNING
+                    warning
                 };
                 *ret += " ";
                 *ret += "[";
-                *ret += crate_name;
-                *ret += "](../";
-                *ret += crate_name;
-                *ret += "/index.html)\n";
+                *ret += label;
+                *ret += "]";
+                if link != label {
+                    *ret += "(";
+                    *ret += link;
+                    *ret += ")";
+                }
+                *ret += "\n";
             }
             for (category, children) in &this.children {
                 *ret += &" ".repeat(4 * depth);
                 *ret += "- 📁 ";
                 *ret += category;
                 *ret += "\n";
-                to_md(children, depth + 1, ret);
+                to_md(children, depth + 1, icons, sort, ret);
+            }
+        }
+
+        static HEAVY_CHECK_MARK_URL: &str =
+            "https://github.githubassets.com/images/icons/emoji/unicode/2714.png";
+        static WARNING_URL: &str =
+            "https://github.githubassets.com/images/icons/emoji/unicode/26a0.png";
+        static HEAVY_CHECK_MARK_PNG: &[u8] = include_bytes!("../assets/2714.png");
+        static WARNING_PNG: &[u8] = include_bytes!("../assets/26a0.png");
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut ret = String::with_capacity(bytes.chunks(3).len() * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0, |n, (i, &b)| n | u32::from(b) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                ret.push(TABLE[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                ret.push('=');
             }
         }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DependencyExt as _, TableOfContents};
+    use crate::testing::tempdir_with_files;
+    use cargo_metadata as cm;
+    use serde_json::json;
+    use std::{path::Path, time::Duration};
+    use url::Url;
+
+    fn dependency(name: &str, source: Option<&str>) -> cm::Dependency {
+        serde_json::from_value(json!({
+            "name": name,
+            "source": source,
+            "req": "*",
+            "kind": null,
+            "optional": false,
+            "uses_default_features": true,
+            "features": [],
+            "target": null,
+            "rename": null,
+            "registry": null,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn to_list_item_path_dependency() {
+        let docs_base_url = &"https://docs.rs/".parse::<Url>().unwrap();
+
+        let dep = dependency("my-lib", None);
+        assert_eq!(
+            (
+                "my-lib (path+../my-lib)".to_owned(),
+                "../my_lib/index.html".to_owned(),
+                None,
+            ),
+            dep.to_list_item(None, Some("../my-lib"), Some("my_lib"), docs_base_url),
+        );
+        // Hidden crates have no docs to link to.
+        assert_eq!(
+            ("my-lib (path+../my-lib)".to_owned(), "".to_owned(), None),
+            dep.to_list_item(None, Some("../my-lib"), None, docs_base_url),
+        );
+        assert_eq!(
+            ("my-lib (unknown)".to_owned(), "".to_owned(), None),
+            dep.to_list_item(None, None, None, docs_base_url),
+        );
+
+        let dep = dependency(
+            "serde",
+            Some("registry+https://github.com/rust-lang/crates.io-index"),
+        );
+        assert_eq!(
+            (
+                "serde ^1".to_owned(),
+                "https://docs.rs/serde/^1".to_owned(),
+                None,
+            ),
+            dep.to_list_item(Some("^1"), None, None, docs_base_url),
+        );
+    }
+
+    #[test]
+    fn toc_depth() {
+        let folders = TableOfContents::folders("libs/graph/dijkstra/Cargo.toml".as_ref());
+        assert_eq!(["libs", "graph"], *folders);
+
+        let labels = |toc: &TableOfContents| toc.crates.keys().cloned().collect::<Vec<_>>();
+
+        let mut toc = TableOfContents::default();
+        toc.insert(&folders, "dijkstra", "", true, Some(0));
+        assert_eq!(["libs/graph/dijkstra"], *labels(&toc));
+        assert!(toc.children.is_empty());
+
+        let mut toc = TableOfContents::default();
+        toc.insert(&folders, "dijkstra", "", true, Some(1));
+        assert!(toc.crates.is_empty());
+        assert_eq!(["graph/dijkstra"], *labels(&toc.children["libs"]));
+        assert!(toc.children["libs"].children.is_empty());
+
+        let mut toc = TableOfContents::default();
+        toc.insert(&folders, "dijkstra", "", true, None);
+        assert_eq!(
+            ["dijkstra"],
+            *labels(&toc.children["libs"].children["graph"]),
+        );
+    }
+
+    #[test]
+    fn files_to_copy() -> anyhow::Result<()> {
+        let tempdir = tempdir_with_files(&[
+            ("Cargo.toml", ""),
+            ("src/lib.rs", ""),
+            ("notes.bak", ""),
+            ("target/debug/a", ""),
+            ("sub/target/debug/a", ""),
+            (".git/config", ""),
+        ])?;
+        let dir = tempdir.path();
+
+        let files = super::files_to_copy(dir, &[dir], &["*.bak".to_owned()])?;
+        let files = files
+            .iter()
+            .map(|p| p.strip_prefix(dir).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!([Path::new("Cargo.toml"), Path::new("src/lib.rs")], *files);
+        Ok(())
+    }
+
+    #[test]
+    fn base64() {
+        for (expected, bytes) in &[
+            ("", &b""[..]),
+            ("Zg==", b"f"),
+            ("Zm8=", b"fo"),
+            ("Zm9v", b"foo"),
+            ("Zm9vYg==", b"foob"),
+            ("Zm9vYmE=", b"fooba"),
+            ("Zm9vYmFy", b"foobar"),
+            ("//79", b"\xff\xfe\xfd"),
+        ] {
+            assert_eq!(*expected, super::base64(bytes));
+        }
+    }
 
-        static HEAVY_CHECK_MARK: &str = r#"<img src="https://github.githubassets.com/images/icons/emoji/unicode/2714.png" alt="✔" title="✔" width="20" height="20">"#;
-        static WARNING: &str = r#"<img src="https://github.githubassets.com/images/icons/emoji/unicode/26a0.png" alt="⚠" title="⚠" width="20" height="20">"#;
+    #[test]
+    fn rfc3339() {
+        for (expected, secs) in &[
+            ("1970-01-01T00:00:00+00:00", 0),
+            ("2000-02-29T12:34:56+00:00", 951_827_696),
+            ("2021-01-01T00:00:00+00:00", 1_609_459_200),
+            ("2100-03-01T23:59:59+00:00", 4_107_628_799),
+        ] {
+            assert_eq!(*expected, super::rfc3339(Duration::from_secs(*secs)));
+        }
     }
 }
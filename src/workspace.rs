@@ -1,43 +1,178 @@
+use crate::{cache, shell::Shell};
 use anyhow::{anyhow, Context as _};
 use camino::Utf8Path;
 use cargo_metadata as cm;
 use ignore::Walk;
 use indexmap::{indexmap, IndexMap};
+use itertools::Itertools as _;
 use maplit::hashset;
+use rayon::iter::{IntoParallelRefIterator as _, ParallelIterator as _};
 use serde::{de::Error as _, Deserialize, Deserializer};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     path::{Path, PathBuf},
     rc::Rc,
 };
 use url::Url;
 
+/// Runs `cargo metadata` for each workspace under `root`, with at most `jobs` processes at once.
+///
+/// If `use_cache` is `true`, the outputs are reused while the manifests and `Cargo.lock` are
+/// unmodified.
 pub(crate) fn list_metadata(
     root: &Path,
+    cargo_flags: &[&str],
+    use_cache: bool,
+    jobs: Option<usize>,
 ) -> anyhow::Result<IndexMap<cm::PackageId, Rc<cm::Metadata>>> {
+    let thread_pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs.unwrap_or(0))
+        .build()?;
+
     let mut metadata_set = indexmap!();
     let visited = &mut hashset!();
-    for manifest_path in manifest_paths(root)? {
-        if visited.contains(&manifest_path) {
-            continue;
-        }
-        let metadata = Rc::new(cargo_metadata(&manifest_path)?);
-        for ws_member in &metadata.workspace_members {
-            metadata_set.insert(ws_member.clone(), metadata.clone());
-            visited.insert(PathBuf::from(&metadata[ws_member].manifest_path));
+    let excluded = &mut vec![];
+    let is_skipped = |manifest_path: &Path, visited: &HashSet<_>, excluded: &[PathBuf]| {
+        visited.contains(manifest_path) || excluded.iter().any(|dir| manifest_path.starts_with(dir))
+    };
+    // Manifests of the same depth cannot be the root of each other, so they are run in parallel.
+    for (_, manifest_paths) in &manifest_paths(root)?
+        .into_iter()
+        .group_by(|p| p.components().count())
+    {
+        let manifest_paths = manifest_paths
+            .filter(|p| !is_skipped(p, visited, excluded))
+            .collect::<Vec<_>>();
+        let outputs = thread_pool.install(|| {
+            manifest_paths
+                .par_iter()
+                .map(|manifest_path| cached_metadata(manifest_path, cargo_flags, use_cache))
+                .collect::<Vec<_>>()
+        });
+        for (manifest_path, metadata) in manifest_paths.iter().zip(outputs) {
+            // Another manifest of the same depth may have been in the same workspace.
+            if is_skipped(manifest_path, visited, excluded) {
+                continue;
+            }
+            let metadata = Rc::new(metadata?);
+            // The root manifest may be a virtual one, which is not the manifest of any package.
+            visited.insert(PathBuf::from(metadata.workspace_root.join("Cargo.toml")));
+            excluded.extend(workspace_exclude(&metadata.workspace_root)?);
+            for ws_member in &metadata.workspace_members {
+                metadata_set.insert(ws_member.clone(), metadata.clone());
+                visited.insert(PathBuf::from(&metadata[ws_member].manifest_path));
+            }
         }
     }
     return Ok(metadata_set);
 
+    /// Lists the manifests, shallower ones first so that the root of each workspace is visited
+    /// before its members.
     fn manifest_paths(root: &Path) -> Result<Vec<PathBuf>, ignore::Error> {
-        Walk::new(root)
+        let mut paths = Walk::new(root)
             .map(|e| e.map(ignore::DirEntry::into_path))
             .filter(|p| !matches!(p, Ok(p) if p.file_name() != Some("Cargo.toml".as_ref())))
-            .collect()
+            .collect::<Result<Vec<_>, _>>()?;
+        paths.sort_by_key(|p| p.components().count());
+        Ok(paths)
     }
 }
 
-fn locate_project(cwd: &Path) -> anyhow::Result<PathBuf> {
+/// Runs `cargo metadata` for the workspace of `manifest_path`, in the same format as
+/// [`list_metadata`].
+pub(crate) fn single_workspace_metadata(
+    manifest_path: &Path,
+    cargo_flags: &[&str],
+    use_cache: bool,
+) -> anyhow::Result<IndexMap<cm::PackageId, Rc<cm::Metadata>>> {
+    let metadata = Rc::new(cached_metadata(manifest_path, cargo_flags, use_cache)?);
+    Ok(metadata
+        .workspace_members
+        .iter()
+        .map(|ws_member| (ws_member.clone(), metadata.clone()))
+        .collect())
+}
+
+/// Runs `cargo metadata`, reusing the output while the manifests and `Cargo.lock` are unmodified
+/// if `use_cache` is `true`.
+fn cached_metadata(
+    manifest_path: &Path,
+    cargo_flags: &[&str],
+    use_cache: bool,
+) -> anyhow::Result<cm::Metadata> {
+    if use_cache {
+        if let Some(metadata) = cache::load_metadata(manifest_path, cargo_flags)? {
+            return Ok(metadata);
+        }
+    }
+    let metadata = cargo_metadata(manifest_path, cargo_flags)?;
+    if use_cache {
+        cache::save_metadata(manifest_path, cargo_flags, &metadata)?;
+    }
+    Ok(metadata)
+}
+
+/// Reads `workspace.exclude`, which `cargo metadata` does not report.
+fn workspace_exclude(workspace_root: &Utf8Path) -> anyhow::Result<Vec<PathBuf>> {
+    let manifest_path = workspace_root.join("Cargo.toml");
+    let Manifest { workspace } = toml::from_str(&xshell::read_file(&manifest_path)?)
+        .with_context(|| format!("could not parse `{}`", manifest_path))?;
+    return Ok(workspace
+        .exclude
+        .iter()
+        .map(|path| workspace_root.join(path).into())
+        .collect());
+
+    #[derive(Deserialize)]
+    struct Manifest {
+        #[serde(default)]
+        workspace: Workspace,
+    }
+
+    #[derive(Deserialize, Default)]
+    struct Workspace {
+        #[serde(default)]
+        exclude: Vec<String>,
+    }
+}
+
+/// Reads the channel in `rust-toolchain.toml` or `rust-toolchain` in `dir`.
+///
+/// `rust-toolchain` may be either in the TOML format or in the legacy format, which consists of
+/// only the channel.
+pub(crate) fn toolchain_file_channel(dir: &Path) -> anyhow::Result<Option<String>> {
+    let path = &if let Some(path) = ["rust-toolchain", "rust-toolchain.toml"]
+        .iter()
+        .map(|file_name| dir.join(file_name))
+        .find(|path| path.exists())
+    {
+        path
+    } else {
+        return Ok(None);
+    };
+    let content = xshell::read_file(path)?;
+    let content = content.trim();
+
+    if path.extension().is_none() && !content.contains(&['\n', '=', '['][..]) {
+        return Ok(Some(content.to_owned()));
+    }
+
+    let ToolchainFile { toolchain } =
+        toml::from_str(content).with_context(|| format!("could not parse `{}`", path.display()))?;
+    return Ok(toolchain.channel);
+
+    #[derive(Deserialize)]
+    struct ToolchainFile {
+        toolchain: Toolchain,
+    }
+
+    #[derive(Deserialize)]
+    struct Toolchain {
+        channel: Option<String>,
+    }
+}
+
+pub(crate) fn locate_project(cwd: &Path) -> anyhow::Result<PathBuf> {
     cwd.ancestors()
         .map(|p| p.join("Cargo.toml"))
         .find(|p| p.exists())
@@ -49,9 +184,18 @@ fn locate_project(cwd: &Path) -> anyhow::Result<PathBuf> {
         })
 }
 
-fn cargo_metadata(manifest_path: &Path) -> anyhow::Result<cm::Metadata> {
+pub(crate) fn cargo_metadata(
+    manifest_path: &Path,
+    cargo_flags: &[&str],
+) -> anyhow::Result<cm::Metadata> {
     cm::MetadataCommand::new()
         .manifest_path(manifest_path)
+        .other_options(
+            cargo_flags
+                .iter()
+                .map(|&flag| flag.to_owned())
+                .collect::<Vec<_>>(),
+        )
         .exec()
         .map_err(|err| match err {
             cm::Error::CargoMetadata { stderr } => {
@@ -61,18 +205,145 @@ fn cargo_metadata(manifest_path: &Path) -> anyhow::Result<cm::Metadata> {
         })
 }
 
+pub(crate) fn normal_deps(
+    metadata: &cm::Metadata,
+) -> HashMap<&cm::PackageId, Vec<(&String, &cm::PackageId)>> {
+    metadata
+        .resolve
+        .as_ref()
+        .unwrap()
+        .nodes
+        .iter()
+        .map(|cm::Node { id, deps, .. }| {
+            let deps = deps
+                .iter()
+                .filter(|cm::NodeDep { dep_kinds, .. }| {
+                    dep_kinds
+                        .iter()
+                        .any(|cm::DepKindInfo { kind, .. }| *kind == cm::DependencyKind::Normal)
+                })
+                .map(|cm::NodeDep { name, pkg, .. }| (name, pkg))
+                .collect::<Vec<_>>();
+            (id, deps)
+        })
+        .collect()
+}
+
+/// Features of `package_id` enabled in the resolved dependency graph.
+pub(crate) fn resolved_features<'a>(
+    metadata: &'a cm::Metadata,
+    package_id: &cm::PackageId,
+) -> &'a [String] {
+    metadata
+        .resolve
+        .as_ref()
+        .unwrap()
+        .nodes
+        .iter()
+        .find(|cm::Node { id, .. }| id == package_id)
+        .map(|cm::Node { features, .. }| &**features)
+        .unwrap_or_default()
+}
+
+/// Finds a cycle in the normal and build dependencies between the workspace members in
+/// `metadata_list`, returning the names of the packages on it with the first one repeated at the
+/// end. If `dev` is `true`, dev-dependencies are followed as well.
+///
+/// `cargo metadata` rejects cycles of normal dependencies, but not the ones through
+/// dev-dependencies.
+pub(crate) fn find_dep_cycle(
+    metadata_list: &IndexMap<cm::PackageId, Rc<cm::Metadata>>,
+    dev: bool,
+) -> Option<Vec<String>> {
+    let mut edges = BTreeMap::<_, BTreeSet<_>>::new();
+    for metadata in metadata_list.values() {
+        for cm::Node { id, deps, .. } in &metadata.resolve.as_ref().unwrap().nodes {
+            if metadata_list.contains_key(id) {
+                edges.entry(id).or_default().extend(
+                    deps.iter()
+                        .filter(|cm::NodeDep { dep_kinds, .. }| {
+                            dep_kinds.iter().any(|cm::DepKindInfo { kind, .. }| {
+                                dev || *kind != cm::DependencyKind::Development
+                            })
+                        })
+                        .map(|cm::NodeDep { pkg, .. }| pkg)
+                        .filter(|pkg| metadata_list.contains_key(*pkg)),
+                );
+            }
+        }
+    }
+    let cycle = find_cycle(&edges)?;
+    Some(
+        cycle
+            .into_iter()
+            .map(|id| metadata_list[*id][*id].name.clone())
+            .collect(),
+    )
+}
+
+/// Finds a cycle in a directed graph with depth-first search.
+fn find_cycle<N: Ord>(edges: &BTreeMap<N, BTreeSet<N>>) -> Option<Vec<&N>> {
+    let mut finished = BTreeSet::new();
+    let mut path = vec![];
+    for node in edges.keys() {
+        if let Some(cycle) = dfs(node, edges, &mut finished, &mut path) {
+            return Some(cycle);
+        }
+    }
+    return None;
+
+    fn dfs<'a, N: Ord>(
+        node: &'a N,
+        edges: &'a BTreeMap<N, BTreeSet<N>>,
+        finished: &mut BTreeSet<&'a N>,
+        path: &mut Vec<&'a N>,
+    ) -> Option<Vec<&'a N>> {
+        if let Some(i) = path.iter().position(|n| *n == node) {
+            let mut cycle = path[i..].to_vec();
+            cycle.push(node);
+            return Some(cycle);
+        }
+        if finished.contains(node) {
+            return None;
+        }
+        path.push(node);
+        for next in edges.get(node).into_iter().flatten() {
+            if let Some(cycle) = dfs(next, edges, finished, path) {
+                return Some(cycle);
+            }
+        }
+        path.pop();
+        finished.insert(node);
+        None
+    }
+}
+
+/// Collects the packages that `roots` depend on, directly or indirectly.
+///
+/// Normal dependencies never form a cycle, since `cargo metadata` fails with the path of the cycle
+/// in that case. The packages are deduplicated only for diamond dependencies.
+pub(crate) fn transitive_normal_deps<'a>(
+    normal_deps: &HashMap<&'a cm::PackageId, Vec<(&'a String, &'a cm::PackageId)>>,
+    roots: impl IntoIterator<Item = &'a cm::PackageId>,
+) -> BTreeSet<&'a cm::PackageId> {
+    let mut deps = BTreeSet::new();
+    let stack = &mut roots.into_iter().collect::<Vec<_>>();
+    while let Some(package_id) = stack.pop() {
+        if deps.insert(package_id) {
+            stack.extend(normal_deps[package_id].iter().map(|(_, pkg)| *pkg));
+        }
+    }
+    deps
+}
+
 pub(crate) trait PackageExt {
     fn metadata(&self) -> serde_json::Result<PackageMetadata>;
     fn manifest_dir(&self) -> &Utf8Path;
     fn lib_target(&self) -> Option<&cm::Target>;
     fn proc_macro_target(&self) -> Option<&cm::Target>;
     fn bin_target(&self, name: &str) -> anyhow::Result<&cm::Target>;
-    fn has_lib_target(&self) -> bool {
-        self.lib_target().is_some()
-    }
-    fn has_proc_macro_target(&self) -> bool {
-        self.proc_macro_target().is_some()
-    }
+    fn bin_problems_in_targets(&self) -> anyhow::Result<HashMap<String, Url>>;
+    fn bin_problems(&self, shell: &mut Shell) -> anyhow::Result<HashMap<String, Url>>;
 }
 
 impl PackageExt for cm::Package {
@@ -107,6 +378,64 @@ impl PackageExt for cm::Package {
             .find(|t| t.name == name && t.kind == ["bin".to_owned()])
             .with_context(|| format!("no bin target named `{}`", name))
     }
+
+    /// Reads `[bin.metadata.cargo-compete] problem = "<url>"` in the `[[bin]]` entries, which
+    /// `cargo metadata` does not report.
+    fn bin_problems_in_targets(&self) -> anyhow::Result<HashMap<String, Url>> {
+        let Manifest { bin } = toml::from_str(&xshell::read_file(&self.manifest_path)?)
+            .with_context(|| format!("could not parse `{}`", self.manifest_path))?;
+        return Ok(bin
+            .into_iter()
+            .flat_map(|Bin { name, metadata }| Some((name?, metadata.cargo_compete?.problem)))
+            .collect());
+
+        #[derive(Deserialize)]
+        struct Manifest {
+            #[serde(default)]
+            bin: Vec<Bin>,
+        }
+
+        #[derive(Deserialize)]
+        struct Bin {
+            name: Option<String>,
+            #[serde(default)]
+            metadata: BinMetadata,
+        }
+
+        #[derive(Deserialize, Default)]
+        #[serde(rename_all = "kebab-case")]
+        struct BinMetadata {
+            cargo_compete: Option<BinMetadataCargoCompete>,
+        }
+
+        #[derive(Deserialize)]
+        struct BinMetadataCargoCompete {
+            #[serde(deserialize_with = "deserialize_problem")]
+            problem: Url,
+        }
+    }
+
+    /// Problem URLs of the bin targets, from both `package.metadata.cargo-compete.bin` and the
+    /// `[[bin]]` entries.
+    fn bin_problems(&self, shell: &mut Shell) -> anyhow::Result<HashMap<String, Url>> {
+        let mut bin = self.metadata()?.cargo_compete.bin;
+        for (name, url) in self.bin_problems_in_targets()? {
+            match bin.get(&name) {
+                Some(package_level_url) if *package_level_url != url => {
+                    shell.warn(format!(
+                        "`{}`: conflicting problem URLs for `{}`. using `{}` in \
+                         `package.metadata.cargo-compete.bin`",
+                        self.name, name, package_level_url,
+                    ))?;
+                }
+                Some(_) => {}
+                None => {
+                    bin.insert(name, url);
+                }
+            }
+        }
+        Ok(bin)
+    }
 }
 
 pub(crate) trait TargetExt {
@@ -124,11 +453,83 @@ impl TargetExt for cm::Target {
     }
 }
 
+pub(crate) trait MetadataExt {
+    fn workspace_metadata(&self) -> anyhow::Result<WorkspaceMetadata>;
+}
+
+impl MetadataExt for cm::Metadata {
+    fn workspace_metadata(&self) -> anyhow::Result<WorkspaceMetadata> {
+        match self.workspace_metadata.clone() {
+            serde_json::Value::Null => Ok(WorkspaceMetadata::default()),
+            metadata => serde_json::from_value(metadata).with_context(|| {
+                format!(
+                    "could not parse `workspace.metadata` in `{}`",
+                    self.workspace_root.join("Cargo.toml"),
+                )
+            }),
+        }
+    }
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct WorkspaceMetadata {
+    #[serde(default)]
+    pub(crate) cargo_cpl: WorkspaceMetadataCargoCpl,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct WorkspaceMetadataCargoCpl {
+    /// Command to test a bin target with, instead of `cargo compete t`. `{manifest_path}` and
+    /// `{bin}` in the arguments are replaced.
+    #[serde(default, deserialize_with = "deserialize_test_command")]
+    pub(crate) test_command: Option<Vec<String>>,
+    // The following are the defaults of the options of `cargo cpl verify gh-pages`, read only from
+    // the workspace nearest to the root of the repository. The command line options take
+    // precedence.
+    /// `--toolchain`.
+    pub(crate) toolchain: Option<String>,
+    /// `--docs-base-url`.
+    pub(crate) docs_base_url: Option<Url>,
+    /// `--copy-exclude`.
+    #[serde(default)]
+    pub(crate) copy_exclude: Vec<String>,
+    /// `--deny-unverified`.
+    #[serde(default)]
+    pub(crate) deny_unverified: bool,
+}
+
+fn deserialize_test_command<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<String>>, D::Error> {
+    let command = Vec::<String>::deserialize(deserializer)?;
+    if command.is_empty() {
+        return Err(D::Error::custom("`test-command` must not be empty"));
+    }
+    Ok(Some(command))
+}
+
 #[derive(Deserialize, Default, Debug)]
 #[serde(rename_all = "kebab-case")]
 pub(crate) struct PackageMetadata {
     #[serde(default)]
     pub(crate) cargo_compete: PackageMetadataCargoCompete,
+    #[serde(default)]
+    pub(crate) cargo_cpl: PackageMetadataCargoCpl,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct PackageMetadataCargoCpl {
+    /// Excludes the package from the docs. It can still be a path dependency of the documented
+    /// packages.
+    #[serde(default)]
+    pub(crate) hidden: bool,
+    /// Label in the table of contents, instead of the crate name.
+    pub(crate) display_name: Option<String>,
+    /// `/`-separated folders in the table of contents, instead of the directories.
+    pub(crate) category: Option<String>,
 }
 
 #[derive(Deserialize, Default, Debug)]
@@ -152,19 +553,144 @@ fn deserialize_bin<'de, D: Deserializer<'de>>(
         #[serde(deserialize_with = "deserialize_problem")]
         problem: Url,
     }
+}
 
-    fn deserialize_problem<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Url, D::Error> {
-        return Problem::deserialize(deserializer)
-            .map(|problem| match problem {
-                Problem::Bare(url) | Problem::Field { url } => url,
-            })
-            .map_err(|_| D::Error::custom("expected `\"<url>\"` or `{ problem = \"<url>\"}`"));
+fn deserialize_problem<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Url, D::Error> {
+    return Problem::deserialize(deserializer)
+        .map(|problem| match problem {
+            Problem::Bare(url) | Problem::Field { url } => url,
+        })
+        .map_err(|_| D::Error::custom("expected `\"<url>\"` or `{ problem = \"<url>\"}`"));
 
-        #[derive(Deserialize)]
-        #[serde(untagged)]
-        enum Problem {
-            Bare(Url),
-            Field { url: Url },
-        }
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Problem {
+        Bare(Url),
+        Field { url: Url },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{tempdir, tempdir_with_files, write_files};
+    use maplit::{btreemap, btreeset};
+    use std::rc::Rc;
+
+    fn package_manifest(name: &str) -> String {
+        format!(
+            "[package]\nname = \"{}\"\nversion = \"0.0.0\"\nedition = \"2018\"\n",
+            name,
+        )
+    }
+
+    #[test]
+    fn list_metadata_virtual_workspace() -> anyhow::Result<()> {
+        let tempdir = tempdir_with_files(&[
+            ("Cargo.toml", "[workspace]\nmembers = [\"a\", \"b\"]\n"),
+            ("a/Cargo.toml", &package_manifest("a")),
+            ("a/src/lib.rs", ""),
+            ("b/Cargo.toml", &package_manifest("b")),
+            ("b/src/lib.rs", ""),
+        ])?;
+
+        let metadata_list = super::list_metadata(tempdir.path(), &[], false, None)?;
+        let names = metadata_list
+            .iter()
+            .map(|(id, metadata)| &*metadata[id].name)
+            .collect::<Vec<_>>();
+        assert_eq!(["a", "b"], *names);
+        // `cargo metadata` is run once at the root.
+        let metadata = metadata_list.values().collect::<Vec<_>>();
+        assert!(Rc::ptr_eq(metadata[0], metadata[1]));
+        Ok(())
+    }
+
+    #[test]
+    fn workspace_of_nested_package() -> anyhow::Result<()> {
+        let tempdir = tempdir()?;
+        let dir = &dunce::canonicalize(tempdir.path())?;
+        write_files(
+            dir,
+            &[
+                ("ws1/Cargo.toml", "[workspace]\nmembers = [\"a\", \"b\"]\n"),
+                ("ws1/a/Cargo.toml", &package_manifest("a")),
+                ("ws1/a/src/lib.rs", ""),
+                ("ws1/b/Cargo.toml", &package_manifest("b")),
+                ("ws1/b/src/lib.rs", ""),
+                ("ws2/Cargo.toml", &package_manifest("c")),
+                ("ws2/src/lib.rs", ""),
+            ],
+        )?;
+
+        let manifest_path = super::locate_project(&dir.join("ws1").join("a").join("src"))?;
+        assert_eq!(dir.join("ws1").join("a").join("Cargo.toml"), manifest_path);
+        let metadata_list = super::single_workspace_metadata(&manifest_path, &[], false)?;
+        let names = metadata_list
+            .iter()
+            .map(|(id, metadata)| &*metadata[id].name)
+            .collect::<Vec<_>>();
+        assert_eq!(["a", "b"], *names);
+        assert_eq!(dir.join("ws1"), metadata_list[0].workspace_root);
+        Ok(())
+    }
+
+    #[test]
+    fn list_metadata_workspace_exclude() -> anyhow::Result<()> {
+        let tempdir = tempdir_with_files(&[
+            (
+                "Cargo.toml",
+                "[workspace]\nmembers = [\"a\"]\nexclude = [\"example\"]\n",
+            ),
+            ("a/Cargo.toml", &package_manifest("a")),
+            ("a/src/lib.rs", ""),
+            // `cargo metadata` would treat this as the root of another workspace.
+            ("example/Cargo.toml", &package_manifest("example")),
+            ("example/src/lib.rs", ""),
+        ])?;
+
+        let metadata_list = super::list_metadata(tempdir.path(), &[], false, None)?;
+        let names = metadata_list
+            .iter()
+            .map(|(id, metadata)| &*metadata[id].name)
+            .collect::<Vec<_>>();
+        assert_eq!(["a"], *names);
+        Ok(())
+    }
+
+    #[test]
+    fn find_cycle() {
+        let edges = btreemap!(
+            1 => btreeset!(2),
+            2 => btreeset!(3),
+            3 => btreeset!(4, 2),
+        );
+        assert_eq!(Some(vec![&2, &3, &2]), super::find_cycle(&edges));
+
+        let edges = btreemap!(1 => btreeset!(2, 3), 2 => btreeset!(3));
+        assert_eq!(None, super::find_cycle(&edges));
+    }
+
+    #[test]
+    fn find_dep_cycle_through_dev_dependency() -> anyhow::Result<()> {
+        let tempdir = tempdir_with_files(&[
+            (
+                "a/Cargo.toml",
+                &(package_manifest("a") + "\n[dev-dependencies]\nb = { path = \"../b\" }\n"),
+            ),
+            ("a/src/lib.rs", ""),
+            (
+                "b/Cargo.toml",
+                &(package_manifest("b") + "\n[dependencies]\na = { path = \"../a\" }\n"),
+            ),
+            ("b/src/lib.rs", ""),
+        ])?;
+
+        let metadata_list = super::list_metadata(tempdir.path(), &[], false, None)?;
+        assert_eq!(None, super::find_dep_cycle(&metadata_list, false));
+        assert_eq!(
+            Some(vec!["a".to_owned(), "b".to_owned(), "a".to_owned()]),
+            super::find_dep_cycle(&metadata_list, true),
+        );
+        Ok(())
     }
 }
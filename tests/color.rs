@@ -0,0 +1,30 @@
+use std::process::Command;
+
+/// Runs `cargo cpl list` in an empty directory, which fails, and returns the stderr.
+fn list_stderr(color: &str) -> anyhow::Result<String> {
+    let tempdir = tempfile::Builder::new()
+        .prefix("cargo-cpl-test-")
+        .tempdir()?;
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-cpl"))
+        .args(["cpl", "--color", color, "list"])
+        .current_dir(tempdir.path())
+        .env_remove("NO_COLOR")
+        .output()?;
+    assert!(!output.status.success());
+    Ok(String::from_utf8(output.stderr)?)
+}
+
+#[test]
+fn color_never() -> anyhow::Result<()> {
+    let stderr = list_stderr("never")?;
+    assert!(stderr.starts_with("error: "), "{:?}", stderr);
+    assert!(!stderr.contains('\x1b'), "{:?}", stderr);
+    Ok(())
+}
+
+#[test]
+fn color_always() -> anyhow::Result<()> {
+    let stderr = list_stderr("always")?;
+    assert!(stderr.contains('\x1b'), "{:?}", stderr);
+    Ok(())
+}